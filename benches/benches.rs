@@ -1,6 +1,7 @@
-use chess_huffman::{EncodedGame, decode_game, encode_pgn};
+use chess_huffman::{EncodedGame, MoveByMoveDecoder, decode_game, encode_game, encode_pgn};
 use criterion::{Criterion, criterion_group, criterion_main};
-use shakmaty::Square;
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{EnPassantMode, Square};
 use std::hint::black_box;
 
 static PGN: &'static str = "1. e4 c5 2. c3 d5 3. exd5 Nf6 4. Bb5+ Bd7 5. Bxd7+ Qxd7 
@@ -42,6 +43,18 @@ fn bench_decode(c: &mut Criterion) {
     });
 }
 
+fn bench_encode_moves(c: &mut Criterion) {
+    let (moves, _) = decode_game(&encode_pgn(black_box(PGN)).unwrap()).unwrap();
+
+    c.bench_function("encode-moves", |b| {
+        b.iter(|| {
+            let encoded = encode_game(black_box(&moves)).unwrap();
+
+            assert!(encoded.inner.len() > 154 / 64);
+        })
+    });
+}
+
 fn bench_encode_pgn_bytes(c: &mut Criterion) {
     let pgn = black_box(PGN);
 
@@ -70,12 +83,74 @@ fn bench_decode_bytes(c: &mut Criterion) {
     });
 }
 
+fn bench_seek_to_ply(c: &mut Criterion) {
+    let bits = encode_pgn(black_box(PGN)).unwrap();
+
+    c.bench_function("seek-to-ply", |b| {
+        b.iter(|| {
+            let mut decoder = MoveByMoveDecoder::new(&bits);
+            assert!(decoder.seek_to_ply(black_box(120)).unwrap());
+
+            assert_eq!(decoder.ply(), 120);
+        })
+    });
+}
+
+fn bench_skip(c: &mut Criterion) {
+    let bits = encode_pgn(black_box(PGN)).unwrap();
+
+    c.bench_function("skip", |b| {
+        b.iter(|| {
+            let mut decoder = MoveByMoveDecoder::new(&bits);
+            let skipped = decoder.skip(black_box(usize::MAX)).unwrap();
+
+            assert_eq!(skipped, 154);
+        })
+    });
+}
+
+fn bench_zobrist_from_positions(c: &mut Criterion) {
+    let bits = encode_pgn(black_box(PGN)).unwrap();
+
+    c.bench_function("zobrist-from-positions", |b| {
+        b.iter(|| {
+            let hashes = MoveByMoveDecoder::new(&bits)
+                .into_iter_positions()
+                .map(|r| r.map(|pos| pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal)))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(hashes.len(), 154);
+        })
+    });
+}
+
+fn bench_zobrist(c: &mut Criterion) {
+    let bits = encode_pgn(black_box(PGN)).unwrap();
+
+    c.bench_function("zobrist", |b| {
+        b.iter(|| {
+            let hashes = MoveByMoveDecoder::new(&bits)
+                .into_iter_zobrist()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(hashes.len(), 154);
+        })
+    });
+}
+
 criterion_group!(
     benches,
     bench_encode_pgn,
     bench_decode,
+    bench_encode_moves,
     bench_encode_pgn_bytes,
-    bench_decode_bytes
+    bench_decode_bytes,
+    bench_seek_to_ply,
+    bench_skip,
+    bench_zobrist_from_positions,
+    bench_zobrist
 );
 
 criterion_main!(benches);
@@ -0,0 +1,595 @@
+use crate::EncodedGame;
+use crate::dedup::move_key;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+const MAGIC: [u8; 4] = *b"CHGA";
+const VERSION: u16 = 1;
+// The id of the codebook that is built from `codes::WEIGHTS`, the only one so far.
+const CODEBOOK_LICHESS_WEIGHTS: u16 = 1;
+
+// Magic, version and codebook id.
+pub(crate) const HEADER_LEN: u64 = 8;
+// Offset, length and flags.
+pub(crate) const INDEX_ENTRY_LEN: u64 = 16;
+// The game number of a deleted game.
+pub(crate) const DELETION_LEN: u64 = 8;
+// Index offset, game count, deletion count, end of the previous segment and magic.
+pub(crate) const TRAILER_LEN: u64 = 36;
+// The index entry flag of deleted games.
+pub(crate) const FLAG_DELETED: u32 = 1;
+
+/// Writes many [`EncodedGame`]s to a single archive, which can be read with [`GameArchive`].
+///
+/// An archive consists of:
+///
+/// * a header: the magic bytes `CHGA`, the format version (`u16`) and the id of the
+///   codebook the games were encoded with (`u16`);
+/// * one or more segments, each consisting of:
+///   * games, each in the format of [`EncodedGame::to_bytes`], one after the other;
+///   * an index entry for each of these games: its offset in the archive (`u64`), its
+///     length (`u32`) and flags (`u32`, with bit 0 set if the game was deleted);
+///   * the game numbers (`u64`) of games of earlier segments that were deleted;
+///   * a trailer: the offset of the index entries (`u64`), the number of games in this
+///     and all earlier segments (`u64`), the number of deleted game numbers (`u64`), the
+///     end of the previous segment (`u64`, 0 for the first segment) and the magic bytes
+///     again.
+///
+/// All integers are little-endian. [`GameArchiveWriter`] writes a single segment, whose index
+/// is only written by [`GameArchiveWriter::finish`]. [`crate::GameArchiveFile`] appends a
+/// segment for each commit, so a commit writes only the games and deletions it adds.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{decode_to_pgn, encode_pgn, GameArchive, GameArchiveWriter};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut writer = GameArchiveWriter::new(vec![])?;
+/// writer.add(&encode_pgn("1. e4 e5 2. Nf3 Nc6")?)?;
+/// writer.add(&encode_pgn("1. d4 d5 2. c4")?)?;
+/// let bytes = writer.finish()?;
+///
+/// let mut archive = GameArchive::open(std::io::Cursor::new(bytes))?;
+/// assert_eq!(archive.len(), 2);
+/// assert_eq!(decode_to_pgn(&archive.game(1)?)?, "1. d4 d5 2. c4 *\n");
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub struct GameArchiveWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    index: Vec<IndexEntry>,
+    // The game numbers by move key, if duplicates are skipped.
+    unique: Option<HashMap<Vec<u8>, usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) offset: u64,
+    pub(crate) len: u32,
+    pub(crate) flags: u32,
+}
+
+impl<W: Write> GameArchiveWriter<W> {
+    /// Starts a new archive by writing its header to `writer`. The archive is expected to
+    /// start at the current position of `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        writer.write_u16::<LittleEndian>(CODEBOOK_LICHESS_WEIGHTS)?;
+        Ok(Self {
+            writer,
+            offset: HEADER_LEN,
+            index: vec![],
+            unique: None,
+        })
+    }
+
+    /// Starts a new archive like [`GameArchiveWriter::new`], that only stores one copy of
+    /// games with the same moves (see [`crate::GameDeduplicator`]). Adding a duplicate game
+    /// writes nothing, and returns the game number of the copy that was added first.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of `writer`.
+    pub fn deduplicating(writer: W) -> io::Result<Self> {
+        let mut archive = Self::new(writer)?;
+        archive.unique = Some(HashMap::new());
+        Ok(archive)
+    }
+
+    /// Appends a game to the archive and returns its game number, which is its index
+    /// in the archive.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of the writer.
+    pub fn add(&mut self, encoded: &EncodedGame) -> io::Result<usize> {
+        let vacant = match &mut self.unique {
+            Some(unique) => match unique.entry(move_key(encoded)) {
+                Entry::Occupied(entry) => return Ok(*entry.get()),
+                Entry::Vacant(entry) => Some(entry),
+            },
+            None => None,
+        };
+        let bytes = encoded.to_bytes();
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "game is too long"))?;
+        self.writer.write_all(&bytes)?;
+        self.index.push(IndexEntry {
+            offset: self.offset,
+            len,
+            flags: 0,
+        });
+        self.offset += u64::from(len);
+        if let Some(entry) = vacant {
+            entry.insert(self.index.len() - 1);
+        }
+        Ok(self.index.len() - 1)
+    }
+
+    /// The number of games added so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Whether no games have been added yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Completes the archive by writing the index and the trailer, and returns the
+    /// underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        for entry in &self.index {
+            entry.write(&mut self.writer)?;
+        }
+        write_trailer(
+            &mut self.writer,
+            &Trailer {
+                index_offset: self.offset,
+                count: self.index.len() as u64,
+                deletions: 0,
+                previous_end: 0,
+            },
+        )?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the games of an archive that was written by [`GameArchiveWriter`].
+///
+/// The index is loaded when the archive is opened, so any game can be read by its
+/// game number with a single seek.
+pub struct GameArchive<R: Read + Seek> {
+    reader: R,
+    codebook_id: u16,
+    index: Vec<IndexEntry>,
+}
+
+impl<R: Read + Seek> GameArchive<R> {
+    /// Opens an archive, that starts at the start of `reader`, by reading its header and index.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if `reader` does not contain a valid
+    /// archive (or one of an unsupported version or codebook), or any I/O error of `reader`.
+    pub fn open(mut reader: R) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+        let codebook_id = parse_header(&header)?;
+
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        let segments = read_segments(archive_len, |offset, buf| {
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(buf)
+        })?;
+
+        let mut index = vec![];
+        let mut entry = [0; INDEX_ENTRY_LEN as usize];
+        let mut deletion = [0; DELETION_LEN as usize];
+        for segment in &segments {
+            reader.seek(SeekFrom::Start(segment.index_offset))?;
+            for _ in segment.first..segment.count {
+                reader.read_exact(&mut entry)?;
+                index.push(IndexEntry::parse(&entry, segment.index_offset)?);
+            }
+            for _ in 0..segment.deletions {
+                reader.read_exact(&mut deletion)?;
+                let n = segment.deleted_game(&deletion)?;
+                index[n as usize].flags |= FLAG_DELETED;
+            }
+        }
+
+        Ok(Self {
+            reader,
+            codebook_id,
+            index,
+        })
+    }
+
+    /// The number of games in the archive, including deleted games.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Whether the archive contains no games, not even deleted ones.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Whether the game with game number `n` was deleted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    #[must_use]
+    pub fn is_deleted(&self, n: usize) -> bool {
+        self.index[n].is_deleted()
+    }
+
+    /// The id of the codebook the games in the archive were encoded with.
+    #[must_use]
+    pub fn codebook_id(&self) -> u16 {
+        self.codebook_id
+    }
+
+    /// Reads the game with game number `n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] if the game was deleted,
+    /// [`io::ErrorKind::InvalidData`] if the stored game is not valid, or any I/O error
+    /// of the reader.
+    pub fn game(&mut self, n: usize) -> io::Result<EncodedGame> {
+        let entry = self.index[n];
+        if entry.is_deleted() {
+            return Err(deleted_error());
+        }
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut bytes = vec![0; entry.len as usize];
+        self.reader.read_exact(&mut bytes)?;
+        EncodedGame::from_bytes_strict(&bytes).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// Returns an iterator over all games in the archive that were not deleted, in order.
+    pub fn games(&mut self) -> impl Iterator<Item = io::Result<EncodedGame>> + '_ {
+        let live: Vec<usize> = (0..self.len()).filter(|&n| !self.is_deleted(n)).collect();
+        live.into_iter().map(|n| self.game(n))
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub(crate) fn into_index(self) -> Vec<IndexEntry> {
+        self.index
+    }
+}
+
+/// Parses the header of an archive and returns its codebook id.
+pub(crate) fn parse_header(header: &[u8]) -> io::Result<u16> {
+    let mut rdr = Cursor::new(header);
+    let mut magic = [0; 4];
+    rdr.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data("not a game archive"));
+    }
+    if rdr.read_u16::<LittleEndian>()? != VERSION {
+        return Err(invalid_data("unsupported archive version"));
+    }
+    let codebook_id = rdr.read_u16::<LittleEndian>()?;
+    if codebook_id != CODEBOOK_LICHESS_WEIGHTS {
+        return Err(invalid_data("unsupported codebook"));
+    }
+    Ok(codebook_id)
+}
+
+/// The trailer of a segment of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Trailer {
+    // The offset of the index entries of the segment.
+    pub(crate) index_offset: u64,
+    // The number of games in the segment and all earlier segments.
+    pub(crate) count: u64,
+    // The number of deleted game numbers after the index entries.
+    pub(crate) deletions: u64,
+    // The end of the previous segment, or 0 if this is the first one.
+    pub(crate) previous_end: u64,
+}
+
+/// A segment of an archive, as found by [`read_segments`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Segment {
+    pub(crate) index_offset: u64,
+    // The game number of the first game in the segment.
+    pub(crate) first: u64,
+    pub(crate) count: u64,
+    pub(crate) deletions: u64,
+}
+
+/// Writes the trailer of a segment.
+pub(crate) fn write_trailer<W: Write>(writer: &mut W, trailer: &Trailer) -> io::Result<()> {
+    writer.write_u64::<LittleEndian>(trailer.index_offset)?;
+    writer.write_u64::<LittleEndian>(trailer.count)?;
+    writer.write_u64::<LittleEndian>(trailer.deletions)?;
+    writer.write_u64::<LittleEndian>(trailer.previous_end)?;
+    writer.write_all(&MAGIC)
+}
+
+/// Parses the trailer of a segment that ends at `end`, and checks that the segment lies
+/// between the header (or the previous segment) and `end`.
+fn parse_trailer(trailer: &[u8], end: u64) -> io::Result<Trailer> {
+    let mut rdr = Cursor::new(trailer);
+    let trailer = Trailer {
+        index_offset: rdr.read_u64::<LittleEndian>()?,
+        count: rdr.read_u64::<LittleEndian>()?,
+        deletions: rdr.read_u64::<LittleEndian>()?,
+        previous_end: rdr.read_u64::<LittleEndian>()?,
+    };
+    let mut magic = [0; 4];
+    rdr.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data("archive trailer is missing"));
+    }
+    let start = if trailer.previous_end == 0 {
+        HEADER_LEN
+    } else {
+        trailer.previous_end
+    };
+    if start > trailer.index_offset
+        || trailer.index_offset > end - TRAILER_LEN
+        || (trailer.previous_end != 0 && trailer.previous_end < HEADER_LEN + TRAILER_LEN)
+    {
+        return Err(invalid_data("archive index does not fit in the archive"));
+    }
+    Ok(trailer)
+}
+
+/// Reads the trailers of all segments of an archive of `archive_len` bytes, with `read_at`
+/// reading the bytes at an offset, and returns the segments in order.
+///
+/// Only the trailers are read, the index entries and deletions of the segments are not.
+pub(crate) fn read_segments<F>(archive_len: u64, mut read_at: F) -> io::Result<Vec<Segment>>
+where
+    F: FnMut(u64, &mut [u8]) -> io::Result<()>,
+{
+    if archive_len < HEADER_LEN + TRAILER_LEN {
+        return Err(invalid_data("archive is too short"));
+    }
+    // The trailers and the ends of their segments, from the last segment to the first.
+    // Each segment ends before the next one starts, so this always ends.
+    let mut trailers = vec![];
+    let mut end = archive_len;
+    loop {
+        let mut bytes = [0; TRAILER_LEN as usize];
+        read_at(end - TRAILER_LEN, &mut bytes)?;
+        let trailer = parse_trailer(&bytes, end)?;
+        trailers.push((trailer, end));
+        if trailer.previous_end == 0 {
+            break;
+        }
+        end = trailer.previous_end;
+    }
+
+    let mut segments = Vec::with_capacity(trailers.len());
+    let mut first = 0;
+    for (trailer, end) in trailers.into_iter().rev() {
+        let fits = trailer
+            .count
+            .checked_sub(first)
+            .and_then(|games| games.checked_mul(INDEX_ENTRY_LEN))
+            .zip(trailer.deletions.checked_mul(DELETION_LEN))
+            .and_then(|(entries, deletions)| entries.checked_add(deletions))
+            .and_then(|len| trailer.index_offset.checked_add(len))
+            == Some(end - TRAILER_LEN);
+        if !fits {
+            return Err(invalid_data("archive index does not fit in the archive"));
+        }
+        segments.push(Segment {
+            index_offset: trailer.index_offset,
+            first,
+            count: trailer.count,
+            deletions: trailer.deletions,
+        });
+        first = trailer.count;
+    }
+    Ok(segments)
+}
+
+impl Segment {
+    /// Parses a deleted game number of the segment, and checks that it belongs to a game of
+    /// the segment or an earlier one.
+    pub(crate) fn deleted_game(&self, bytes: &[u8]) -> io::Result<u64> {
+        let n = LittleEndian::read_u64(bytes);
+        if n >= self.count {
+            return Err(invalid_data("deleted game number out of range"));
+        }
+        Ok(n)
+    }
+}
+
+impl IndexEntry {
+    /// Parses an index entry, and checks that the game it points to lies between the header
+    /// and the index.
+    pub(crate) fn parse(bytes: &[u8], index_offset: u64) -> io::Result<Self> {
+        let mut rdr = Cursor::new(bytes);
+        let entry = IndexEntry {
+            offset: rdr.read_u64::<LittleEndian>()?,
+            len: rdr.read_u32::<LittleEndian>()?,
+            flags: rdr.read_u32::<LittleEndian>()?,
+        };
+        if entry.len == 0
+            || entry.offset < HEADER_LEN
+            || entry
+                .offset
+                .checked_add(u64::from(entry.len))
+                .is_none_or(|end| end > index_offset)
+        {
+            return Err(invalid_data("archive index entry is out of bounds"));
+        }
+        Ok(entry)
+    }
+
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.len)?;
+        writer.write_u32::<LittleEndian>(self.flags)
+    }
+
+    pub(crate) fn is_deleted(&self) -> bool {
+        self.flags & FLAG_DELETED != 0
+    }
+}
+
+pub(crate) fn deleted_error() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "game was deleted")
+}
+
+pub(crate) fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    error: E,
+) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::games;
+    use std::io::Cursor;
+
+    fn write_archive(games: &[EncodedGame]) -> Vec<u8> {
+        let mut writer = GameArchiveWriter::new(vec![]).unwrap();
+        for (n, game) in games.iter().enumerate() {
+            assert_eq!(writer.add(game).unwrap(), n);
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn archive_roundtrip() {
+        let games = games();
+        let bytes = write_archive(&games);
+        let payload_len: usize = games.iter().map(|g| g.to_bytes().len()).sum();
+        assert_eq!(
+            bytes.len() as u64,
+            HEADER_LEN + payload_len as u64 + 4 * INDEX_ENTRY_LEN + TRAILER_LEN
+        );
+
+        let mut archive = GameArchive::open(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), games.len());
+        assert_eq!(archive.codebook_id(), CODEBOOK_LICHESS_WEIGHTS);
+        // `to_bytes` drops unused words, so compare through it.
+        for n in [3, 0, 2, 1] {
+            assert_eq!(archive.game(n).unwrap().to_bytes(), games[n].to_bytes());
+        }
+        let read: Vec<_> = archive.games().map(Result::unwrap).collect();
+        assert_eq!(read.len(), games.len());
+
+        let empty = GameArchiveWriter::new(vec![]).unwrap().finish().unwrap();
+        assert!(GameArchive::open(Cursor::new(empty)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn deduplicating_archive() {
+        let games = games();
+        let mut writer = GameArchiveWriter::deduplicating(vec![]).unwrap();
+        for (n, game) in games.iter().enumerate() {
+            assert_eq!(writer.add(game).unwrap(), n);
+        }
+        let copy = EncodedGame::from_bytes(&games[2].to_bytes());
+        assert_ne!(copy, games[2]);
+        assert_eq!(writer.add(&copy).unwrap(), 2);
+        assert_eq!(writer.add(&games[0]).unwrap(), 0);
+        assert_eq!(writer.len(), games.len());
+        assert_eq!(writer.finish().unwrap(), write_archive(&games));
+    }
+
+    #[test]
+    fn invalid_archives() {
+        let bytes = write_archive(&games());
+        let open = |bytes: &[u8]| GameArchive::open(Cursor::new(bytes.to_vec())).map(|_| ());
+
+        assert!(open(&bytes).is_ok());
+        assert!(open(&bytes[..bytes.len() - 1]).is_err());
+        assert!(open(&bytes[1..]).is_err());
+        assert!(open(&[]).is_err());
+
+        let mut unknown_version = bytes.clone();
+        unknown_version[4] = 2;
+        assert_eq!(
+            open(&unknown_version).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut bad_count = bytes.clone();
+        let count_at = bytes.len() - TRAILER_LEN as usize + 8;
+        bad_count[count_at] += 1;
+        assert_eq!(
+            open(&bad_count).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    // Appends a segment without games that deletes `deleted` to an archive of `count` games.
+    fn append_deletions(bytes: &[u8], count: u64, deleted: &[u64]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        let trailer = Trailer {
+            index_offset: bytes.len() as u64,
+            count,
+            deletions: deleted.len() as u64,
+            previous_end: bytes.len() as u64,
+        };
+        for n in deleted {
+            bytes.write_u64::<LittleEndian>(*n).unwrap();
+        }
+        write_trailer(&mut bytes, &trailer).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn chained_segments() {
+        let games = games();
+        let bytes = write_archive(&games);
+        let count = games.len() as u64;
+        let chained = append_deletions(&append_deletions(&bytes, count, &[1]), count, &[3, 1]);
+        let mut archive = GameArchive::open(Cursor::new(chained)).unwrap();
+        assert_eq!(archive.len(), games.len());
+        let deleted: Vec<_> = (0..games.len())
+            .filter(|&n| archive.is_deleted(n))
+            .collect();
+        assert_eq!(deleted, [1, 3]);
+        assert_eq!(archive.game(2).unwrap().to_bytes(), games[2].to_bytes());
+
+        let open = |bytes: Vec<u8>| GameArchive::open(Cursor::new(bytes)).map(|_| ());
+        assert!(open(append_deletions(&bytes, count, &[count])).is_err());
+        // A segment cannot have fewer games than the previous one.
+        assert!(open(append_deletions(&bytes, count - 1, &[])).is_err());
+        let mut bad_previous = append_deletions(&bytes, count, &[]);
+        let previous_at = bad_previous.len() - 12;
+        bad_previous[previous_at] += 1;
+        assert!(open(bad_previous).is_err());
+    }
+}
@@ -0,0 +1,655 @@
+use crate::EncodedGame;
+use crate::archive::{
+    FLAG_DELETED, GameArchive, GameArchiveWriter, IndexEntry, Trailer, deleted_error, invalid_data,
+    write_trailer,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_MAGIC: [u8; 4] = *b"CHGJ";
+// Magic and the length of the archive before the change.
+const JOURNAL_LEN: usize = 12;
+const JOURNAL_SUFFIX: &str = ".journal";
+const LOCK_SUFFIX: &str = ".lock";
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// An archive file (in the format of [`GameArchiveWriter`]) that can be changed: games can
+/// be appended and deleted, and the archive can be compacted.
+///
+/// Changes are collected in memory, and written by [`GameArchiveFile::commit`] without
+/// rewriting the file: a segment is appended to the file with the new games, their index
+/// entries, the game numbers of the deleted games and a trailer that points to the previous
+/// one, so the bytes a commit writes grow with its changes, not with the archive. Before that,
+/// the old length of the file is written to a journal next to it (`<path>.journal`), which
+/// is removed once the commit is complete. If a commit is interrupted, for example by a
+/// crash, the archive is rolled back to its old length the next time it is opened.
+///
+/// Deleted games keep their game numbers and take up space until the archive is compacted
+/// with [`GameArchiveFile::compact`], which writes the remaining games to a new file
+/// (`<path>.tmp`) that then atomically replaces the archive.
+///
+/// If an I/O error occurs during a commit or compaction, the archive has to be opened again
+/// (which recovers it) before it can be changed further.
+///
+/// An archive can only be open once at a time, also across processes: while it is open,
+/// a lock file next to it (`<path>.lock`) is locked. The lock file is left in place when
+/// the archive is closed.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use chess_huffman::{encode_pgn, GameArchiveFile};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut archive = GameArchiveFile::open("games.chga")?;
+/// archive.append(&encode_pgn("1. e4 e5 2. Nf3 Nc6")?)?;
+/// archive.delete(12);
+/// archive.commit()?;
+///
+/// // Game numbers change when the archive is compacted.
+/// let game_numbers = archive.compact()?;
+/// assert_eq!(game_numbers[12], None);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct GameArchiveFile {
+    path: PathBuf,
+    // The lock file, which stays locked until the archive is dropped.
+    _lock: File,
+    file: File,
+    // The index entries of all games, including the appended games that are not committed
+    // yet, whose offsets are only known when they are written.
+    index: Vec<IndexEntry>,
+    // The bytes of the appended games that are not committed yet.
+    pending: Vec<Vec<u8>>,
+    // The committed games that were deleted since the last commit.
+    deleted: Vec<u64>,
+    // Whether a commit or compaction failed.
+    failed: bool,
+}
+
+// The points at which a commit or compaction can be interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    JournalWritten,
+    GamesWritten,
+    IndexWritten,
+    TrailerWritten,
+    CompactionWritten,
+    CompactionRenamed,
+}
+
+impl GameArchiveFile {
+    /// Creates a new, empty archive file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::AlreadyExists`] if there is a file at `path`,
+    /// [`io::ErrorKind::WouldBlock`] if an archive at `path` is open, or any I/O error when
+    /// creating the file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let lock = lock(path)?;
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "archive already exists",
+            ));
+        }
+        // Write the archive under another name first, so a crash cannot leave an empty
+        // or partial file behind.
+        let temp = sibling(path, TEMP_SUFFIX);
+        let file = GameArchiveWriter::new(File::create(&temp)?)?.finish()?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        sync_dir(path)?;
+        Self::open_locked(path.to_path_buf(), lock)
+    }
+
+    /// Opens the archive file at `path` for changing it.
+    ///
+    /// If a commit was interrupted, the archive is first rolled back to its state before
+    /// the commit, by truncating the file (which must therefore not be memory-mapped at that
+    /// time). If a compaction was interrupted, its unfinished new file is removed.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::WouldBlock`] if the archive is already open,
+    /// [`io::ErrorKind::InvalidData`] if the file is not a valid archive, or any I/O error
+    /// when opening or recovering the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let lock = lock(&path)?;
+        Self::open_locked(path, lock)
+    }
+
+    // Opens the archive at `path`, whose lock file was locked as `lock`.
+    fn open_locked(path: PathBuf, lock: File) -> io::Result<Self> {
+        remove_if_exists(&sibling(&path, TEMP_SUFFIX))?;
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        recover(&path, &mut file)?;
+        let index = GameArchive::open(&mut file)?.into_index();
+        Ok(Self {
+            path,
+            _lock: lock,
+            file,
+            index,
+            pending: vec![],
+            deleted: vec![],
+            failed: false,
+        })
+    }
+
+    /// The number of games in the archive, including deleted games and appended games that
+    /// are not committed yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Whether the archive contains no games, not even deleted ones.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Whether the game with game number `n` was deleted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    #[must_use]
+    pub fn is_deleted(&self, n: usize) -> bool {
+        self.index[n].is_deleted()
+    }
+
+    /// Reads the game with game number `n`, which may be an appended game that is not
+    /// committed yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] if the game was deleted,
+    /// [`io::ErrorKind::InvalidData`] if the stored game is not valid, or any I/O error
+    /// of the file.
+    pub fn game(&mut self, n: usize) -> io::Result<EncodedGame> {
+        let entry = self.index[n];
+        if entry.is_deleted() {
+            return Err(deleted_error());
+        }
+        let committed = self.index.len() - self.pending.len();
+        let bytes = if n >= committed {
+            self.pending[n - committed].clone()
+        } else {
+            let mut bytes = vec![0; entry.len as usize];
+            self.file.seek(SeekFrom::Start(entry.offset))?;
+            self.file.read_exact(&mut bytes)?;
+            bytes
+        };
+        EncodedGame::from_bytes_strict(&bytes).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// Appends a game and returns its game number. The game is only written by
+    /// [`GameArchiveFile::commit`].
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] if the game is too long, or any
+    /// error of an earlier commit or compaction.
+    pub fn append(&mut self, encoded: &EncodedGame) -> io::Result<usize> {
+        self.check_not_failed()?;
+        let bytes = encoded.to_bytes();
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "game is too long"))?;
+        self.index.push(IndexEntry {
+            offset: 0,
+            len,
+            flags: 0,
+        });
+        self.pending.push(bytes);
+        Ok(self.index.len() - 1)
+    }
+
+    /// Deletes the game with game number `n`, and returns whether it was not deleted yet.
+    /// The deletion is only written by [`GameArchiveFile::commit`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    pub fn delete(&mut self, n: usize) -> bool {
+        let committed = self.index.len() - self.pending.len();
+        let entry = &mut self.index[n];
+        let was_live = !entry.is_deleted();
+        entry.flags |= FLAG_DELETED;
+        // Appended games are written with their flags.
+        if was_live && n < committed {
+            self.deleted.push(n as u64);
+        }
+        was_live
+    }
+
+    /// Writes the appended games and the deletions since the last commit to the file.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of the file or the journal, or of an earlier commit or compaction.
+    /// The archive then has to be opened again.
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.check_not_failed()?;
+        if self.pending.is_empty() && self.deleted.is_empty() {
+            return Ok(());
+        }
+        self.failed = true;
+
+        let old_len = self.file.seek(SeekFrom::End(0))?;
+        let mut journal = File::create(sibling(&self.path, JOURNAL_SUFFIX))?;
+        let mut bytes = JOURNAL_MAGIC.to_vec();
+        bytes.extend_from_slice(&old_len.to_le_bytes());
+        journal.write_all(&bytes)?;
+        journal.sync_all()?;
+        sync_dir(&self.path)?;
+        crash_point(Stage::JournalWritten)?;
+
+        let mut writer = BufWriter::new(&self.file);
+        let mut offset = old_len;
+        let committed = self.index.len() - self.pending.len();
+        for (entry, bytes) in self.index[committed..].iter_mut().zip(&self.pending) {
+            writer.write_all(bytes)?;
+            entry.offset = offset;
+            offset += u64::from(entry.len);
+        }
+        writer.flush()?;
+        crash_point(Stage::GamesWritten)?;
+
+        for entry in &self.index[committed..] {
+            entry.write(&mut writer)?;
+        }
+        for &n in &self.deleted {
+            writer.write_all(&n.to_le_bytes())?;
+        }
+        writer.flush()?;
+        crash_point(Stage::IndexWritten)?;
+
+        let trailer = Trailer {
+            index_offset: offset,
+            count: self.index.len() as u64,
+            deletions: self.deleted.len() as u64,
+            previous_end: old_len,
+        };
+        write_trailer(&mut writer, &trailer)?;
+        writer.flush()?;
+        drop(writer);
+        self.file.sync_all()?;
+        crash_point(Stage::TrailerWritten)?;
+
+        fs::remove_file(sibling(&self.path, JOURNAL_SUFFIX))?;
+        sync_dir(&self.path)?;
+        self.pending.clear();
+        self.deleted.clear();
+        self.failed = false;
+        Ok(())
+    }
+
+    /// Commits the pending changes, and then rewrites the archive with only the games that
+    /// were not deleted, in order. Returns the new game number of each old game (`None` for
+    /// deleted games).
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if a stored game is not valid, any
+    /// I/O error of the files, or any error of an earlier commit or compaction. The archive
+    /// then has to be opened again.
+    pub fn compact(&mut self) -> io::Result<Vec<Option<usize>>> {
+        self.commit()?;
+        self.failed = true;
+
+        let temp = sibling(&self.path, TEMP_SUFFIX);
+        let mut writer = GameArchiveWriter::new(BufWriter::new(File::create(&temp)?))?;
+        let mut game_numbers = Vec::with_capacity(self.len());
+        for n in 0..self.len() {
+            game_numbers.push(if self.is_deleted(n) {
+                None
+            } else {
+                Some(writer.add(&self.game(n)?)?)
+            });
+        }
+        let file = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        crash_point(Stage::CompactionWritten)?;
+
+        fs::rename(&temp, &self.path)?;
+        crash_point(Stage::CompactionRenamed)?;
+        sync_dir(&self.path)?;
+
+        // The new file has no journal or temporary file to recover, and the lock file
+        // stays locked.
+        let mut file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.index = GameArchive::open(&mut file)?.into_index();
+        self.file = file;
+        self.failed = false;
+        Ok(game_numbers)
+    }
+
+    fn check_not_failed(&self) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other(
+                "an earlier change failed, the archive has to be opened again",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(test))]
+#[allow(clippy::unnecessary_wraps)]
+fn crash_point(_stage: Stage) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+thread_local! {
+    // The stage at which the tests on this thread simulate a crash.
+    static CRASH_AT: std::cell::Cell<Option<Stage>> = const { std::cell::Cell::new(None) };
+}
+
+// Simulates a crash if the tests set `CRASH_AT` to `stage`.
+#[cfg(test)]
+fn crash_point(stage: Stage) -> io::Result<()> {
+    if CRASH_AT.get() == Some(stage) {
+        return Err(io::Error::other(format!("crash after {stage:?}")));
+    }
+    Ok(())
+}
+
+// Locks the lock file of the archive at `path`, so no other `GameArchiveFile` can change or
+// recover the archive while the returned file is open.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(sibling(path, LOCK_SUFFIX))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "archive is already open",
+        )),
+        Err(fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+// Rolls back an interrupted commit of the archive at `path`, if any.
+fn recover(path: &Path, file: &mut File) -> io::Result<()> {
+    let journal_path = sibling(path, JOURNAL_SUFFIX);
+    let journal = match fs::read(&journal_path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // The journal is synced before the archive is changed, so if it is incomplete, the
+    // archive was not changed yet.
+    if journal.len() == JOURNAL_LEN && journal[..4] == JOURNAL_MAGIC {
+        let old_len = LittleEndian::read_u64(&journal[4..]);
+        if old_len > file.metadata()?.len() {
+            return Err(invalid_data("archive journal does not match the archive"));
+        }
+        file.set_len(old_len)?;
+        file.sync_all()?;
+    }
+    fs::remove_file(&journal_path)?;
+    sync_dir(path)
+}
+
+// Returns `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Makes the creation, removal or renaming of the file at `path` durable.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{GAMES, TempDir};
+    use crate::{GameArchive, encode_pgn};
+
+    fn game(n: usize) -> EncodedGame {
+        encode_pgn(GAMES[n]).unwrap()
+    }
+
+    // The path of the archive in `dir`.
+    fn archive_path(dir: &TempDir) -> PathBuf {
+        dir.file("games.chga")
+    }
+
+    // The games of the archive at `path`, `None` for deleted games, as read by `GameArchive`.
+    fn contents(path: &Path) -> Vec<Option<Vec<u8>>> {
+        let mut archive = GameArchive::open(File::open(path).unwrap()).unwrap();
+        (0..archive.len())
+            .map(|n| match archive.game(n) {
+                Ok(game) => Some(game.to_bytes()),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Creates an archive with games 0 and 1, and deletes game 1.
+    fn setup(dir: &TempDir) -> GameArchiveFile {
+        let mut archive = GameArchiveFile::create(archive_path(dir)).unwrap();
+        assert_eq!(archive.append(&game(0)).unwrap(), 0);
+        assert_eq!(archive.append(&game(1)).unwrap(), 1);
+        archive.commit().unwrap();
+        assert!(archive.delete(1));
+        archive.commit().unwrap();
+        archive
+    }
+
+    #[test]
+    fn append_delete_compact() {
+        let dir = TempDir::new("append-delete-compact");
+        let mut archive = setup(&dir);
+        assert!(GameArchiveFile::create(archive_path(&dir)).is_err());
+        assert!(!archive.delete(1));
+        assert_eq!(archive.append(&game(2)).unwrap(), 2);
+        assert_eq!(archive.game(2).unwrap().to_bytes(), game(2).to_bytes());
+        archive.commit().unwrap();
+        drop(archive);
+
+        let expected = vec![Some(game(0).to_bytes()), None, Some(game(2).to_bytes())];
+        assert_eq!(contents(&archive_path(&dir)), expected);
+        let mut archive = GameArchiveFile::open(archive_path(&dir)).unwrap();
+        assert_eq!(archive.len(), 3);
+        assert!(archive.is_deleted(1));
+        assert_eq!(archive.game(1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let mut reader = GameArchive::open(File::open(archive_path(&dir)).unwrap()).unwrap();
+        assert_eq!(reader.games().count(), 2);
+
+        let old_len = fs::metadata(archive_path(&dir)).unwrap().len();
+        archive.append(&game(3)).unwrap();
+        assert_eq!(
+            archive.compact().unwrap(),
+            [Some(0), None, Some(1), Some(2)]
+        );
+        assert_eq!(archive.len(), 3);
+        assert!(fs::metadata(archive_path(&dir)).unwrap().len() < old_len);
+        assert_eq!(
+            contents(&archive_path(&dir)),
+            [
+                Some(game(0).to_bytes()),
+                Some(game(2).to_bytes()),
+                Some(game(3).to_bytes())
+            ]
+        );
+        // Only the archive and its lock file are left.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn commits_only_write_their_changes() {
+        use crate::archive::{DELETION_LEN, INDEX_ENTRY_LEN, TRAILER_LEN};
+
+        let dir = TempDir::new("commit-size");
+        let path = archive_path(&dir);
+        let mut archive = GameArchiveFile::create(&path).unwrap();
+        for _ in 0..100 {
+            archive.append(&game(0)).unwrap();
+        }
+        archive.commit().unwrap();
+        let len = || fs::metadata(&path).unwrap().len();
+
+        let before = len();
+        archive.delete(10);
+        archive.delete(20);
+        archive.commit().unwrap();
+        assert_eq!(len() - before, 2 * DELETION_LEN + TRAILER_LEN);
+
+        let before = len();
+        let new = archive.append(&game(1)).unwrap();
+        archive.delete(new);
+        archive.delete(30);
+        archive.commit().unwrap();
+        let game_len = game(1).to_bytes().len() as u64;
+        assert_eq!(
+            len() - before,
+            game_len + INDEX_ENTRY_LEN + DELETION_LEN + TRAILER_LEN
+        );
+        drop(archive);
+
+        let contents = contents(&path);
+        assert_eq!(contents.len(), 101);
+        let deleted: Vec<_> = (0..101).filter(|&n| contents[n].is_none()).collect();
+        assert_eq!(deleted, [10, 20, 30, 100]);
+        let archive = GameArchiveFile::open(&path).unwrap();
+        assert!(archive.is_deleted(30) && archive.is_deleted(100) && !archive.is_deleted(40));
+    }
+
+    #[test]
+    fn archives_can_only_be_open_once() {
+        let dir = TempDir::new("open-once");
+        let mut archive = setup(&dir);
+        archive.append(&game(2)).unwrap();
+
+        let err = GameArchiveFile::open(archive_path(&dir)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        let err = GameArchiveFile::create(archive_path(&dir)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // The archive stays locked through a compaction, which replaces the file.
+        archive.compact().unwrap();
+        let err = GameArchiveFile::open(archive_path(&dir)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        drop(archive);
+
+        let archive = GameArchiveFile::open(archive_path(&dir)).unwrap();
+        assert_eq!(archive.len(), 2);
+    }
+
+    #[test]
+    fn interrupted_commits_are_rolled_back() {
+        for stage in [
+            Stage::JournalWritten,
+            Stage::GamesWritten,
+            Stage::IndexWritten,
+            Stage::TrailerWritten,
+        ] {
+            let dir = TempDir::new(&format!("commit-{stage:?}"));
+            let mut archive = setup(&dir);
+            let before = fs::read(archive_path(&dir)).unwrap();
+
+            CRASH_AT.set(Some(stage));
+            archive.append(&game(2)).unwrap();
+            archive.delete(0);
+            assert!(archive.commit().is_err(), "{stage:?}");
+            CRASH_AT.set(None);
+            assert!(archive.append(&game(3)).is_err());
+            assert!(archive.commit().is_err());
+            drop(archive);
+
+            let mut archive = GameArchiveFile::open(archive_path(&dir)).unwrap();
+            assert_eq!(fs::read(archive_path(&dir)).unwrap(), before, "{stage:?}");
+            assert_eq!(archive.len(), 2);
+            assert!(!archive.is_deleted(0));
+            assert!(!sibling(&archive_path(&dir), JOURNAL_SUFFIX).exists());
+
+            // The archive can be changed again.
+            archive.append(&game(2)).unwrap();
+            archive.commit().unwrap();
+            assert_eq!(contents(&archive_path(&dir)).len(), 3);
+        }
+    }
+
+    #[test]
+    fn interrupted_compactions() {
+        for stage in [Stage::CompactionWritten, Stage::CompactionRenamed] {
+            let dir = TempDir::new(&format!("compaction-{stage:?}"));
+            let mut archive = setup(&dir);
+            CRASH_AT.set(Some(stage));
+            assert!(archive.compact().is_err());
+            CRASH_AT.set(None);
+            drop(archive);
+
+            let archive = GameArchiveFile::open(archive_path(&dir)).unwrap();
+            let expected = if stage == Stage::CompactionWritten {
+                vec![Some(game(0).to_bytes()), None]
+            } else {
+                vec![Some(game(0).to_bytes())]
+            };
+            assert_eq!(contents(&archive_path(&dir)), expected, "{stage:?}");
+            assert_eq!(archive.len(), expected.len());
+            assert!(!sibling(&archive_path(&dir), TEMP_SUFFIX).exists());
+        }
+    }
+
+    #[test]
+    fn incomplete_journals() {
+        let dir = TempDir::new("incomplete-journal");
+        drop(setup(&dir));
+        let before = fs::read(archive_path(&dir)).unwrap();
+        let journal = sibling(&archive_path(&dir), JOURNAL_SUFFIX);
+
+        // A journal that was not completely written is ignored.
+        fs::write(&journal, &JOURNAL_MAGIC[..3]).unwrap();
+        drop(GameArchiveFile::open(archive_path(&dir)).unwrap());
+        assert_eq!(fs::read(archive_path(&dir)).unwrap(), before);
+        assert!(!journal.exists());
+
+        // A journal of a longer archive is an error.
+        let mut bytes = JOURNAL_MAGIC.to_vec();
+        bytes.extend_from_slice(&(before.len() as u64 + 1).to_le_bytes());
+        fs::write(&journal, &bytes).unwrap();
+        let err = GameArchiveFile::open(archive_path(&dir)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{DecodeResult, EncodeResult, EncodedGame, decode_game, encode_game, encode_pgn};
+use rayon::prelude::*;
+use shakmaty::{Chess, Move};
+use std::borrow::Borrow;
+
+/// Encodes a batch of PGN games in parallel.
+///
+/// `pgns` can be any iterator of strings or byte slices, such as a slice, a `Vec` or
+/// the games split from a larger input. It is collected first, and then the games are
+/// encoded in parallel. The results are in the same order as the input, with an error
+/// for each game that could not be encoded.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::encode_pgn_batch;
+/// let results = encode_pgn_batch(&["1. e4 e5 2. Nf3", "1. e4 e4"]);
+/// assert!(results[0].is_ok());
+/// assert!(results[1].is_err());
+///
+/// let results = encode_pgn_batch("1. d4 d5\n1. c4 e5".lines());
+/// assert_eq!(results.len(), 2);
+/// ```
+pub fn encode_pgn_batch<I>(pgns: I) -> Vec<EncodeResult<EncodedGame>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]> + Send,
+{
+    let pgns: Vec<I::Item> = pgns.into_iter().collect();
+    pgns.into_par_iter().map(encode_pgn).collect()
+}
+
+/// Encodes a batch of games, given as lists of moves, in parallel.
+///
+/// Like [`encode_pgn_batch`], `games` can be any iterator and is collected first.
+/// The results are in the same order as the input, with an error for each game that
+/// could not be encoded.
+pub fn encode_game_batch<I>(games: I) -> Vec<EncodeResult<EncodedGame>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[Move]> + Send,
+{
+    let games: Vec<I::Item> = games.into_iter().collect();
+    games
+        .into_par_iter()
+        .map(|moves| encode_game(moves.as_ref()))
+        .collect()
+}
+
+/// Decodes a batch of encoded games in parallel, into their moves and positions
+/// (like [`decode_game`]).
+///
+/// `encoded` can be any iterator of owned games or references to them, and is collected
+/// first. The results are in the same order as the input, with an error for each game
+/// that could not be decoded.
+pub fn decode_game_batch<I>(encoded: I) -> Vec<DecodeResult<(Vec<Move>, Vec<Chess>)>>
+where
+    I: IntoIterator,
+    I::Item: Borrow<EncodedGame> + Send,
+{
+    let encoded: Vec<I::Item> = encoded.into_iter().collect();
+    encoded
+        .into_par_iter()
+        .map(|encoded| decode_game(encoded.borrow()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameEncodeErrorKind;
+
+    #[test]
+    fn batch_roundtrip_keeps_order() {
+        let pgns: Vec<String> = (0..200)
+            .map(|i| match i % 3 {
+                0 => "1. e4 e5 2. Nf3 Nc6 3. Bb5".to_owned(),
+                1 => "1. d4 d5 2. c4".to_owned(),
+                _ => format!("1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# {i}"),
+            })
+            .collect();
+
+        let encoded = encode_pgn_batch(&pgns);
+        assert_eq!(encoded.len(), pgns.len());
+        let encoded: Vec<EncodedGame> = encoded.into_iter().map(Result::unwrap).collect();
+        for (pgn, game) in pgns.iter().zip(&encoded) {
+            assert_eq!(game, &encode_pgn(pgn).unwrap());
+        }
+
+        let decoded = decode_game_batch(&encoded);
+        let moves: Vec<Vec<Move>> = decoded.into_iter().map(|r| r.unwrap().0).collect();
+        for (game, moves) in encoded.iter().zip(&moves) {
+            assert_eq!(&decode_game(game).unwrap().0, moves);
+        }
+
+        let reencoded = encode_game_batch(&moves);
+        for (game, reencoded) in encoded.iter().zip(reencoded) {
+            assert_eq!(game, &reencoded.unwrap());
+        }
+    }
+
+    #[test]
+    fn batch_reports_errors_per_item() {
+        let results = encode_pgn_batch(vec!["1. e4 e5", "1. e4 Ke7", "1. e4 e5 2. Ke2"]);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().kind,
+            GameEncodeErrorKind::SanError
+        );
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn batch_accepts_sequential_iterators() {
+        let pgn = "1. e4 e5\n1. d4 Kd7 2. Kd2\n1. c4";
+        let results = encode_pgn_batch(pgn.lines());
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+
+        let encoded = results.into_iter().filter_map(Result::ok);
+        let decoded = decode_game_batch(encoded);
+        let first_moves: Vec<String> = decoded
+            .iter()
+            .map(|r| r.as_ref().unwrap().0[0].to_string())
+            .collect();
+        assert_eq!(first_moves, ["e2-e4", "c2-c4"]);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{DecodeResult, EncodedGame, MoveByMoveDecoder};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use shakmaty::packed::PackedSetup;
+use shakmaty::{CastlingMode, Chess, EnPassantMode, FromSetup, Position};
+use std::io::{Cursor, Read};
+
+/// A table of decoder states at every `interval` plies of an encoded game, so a
+/// [`MoveByMoveDecoder`] can jump close to any ply instead of decoding the game from the start.
+///
+/// The table is stored next to the [`EncodedGame`] it was built from and is only valid for
+/// that game. Each entry holds the bit offset of the next move and a compactly packed
+/// snapshot of the position at that point.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, Checkpoints, MoveByMoveDecoder};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let encoded = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. exd5 exd5 5. d4 Nc6")?;
+/// let checkpoints = Checkpoints::new(&encoded, 4)?;
+///
+/// let mut decoder = MoveByMoveDecoder::new(&encoded);
+/// // Starts decoding from the checkpoint at ply 8, rather than from the initial position.
+/// decoder.seek_to_ply_with_checkpoints(9, &checkpoints)?;
+/// assert_eq!(decoder.next_move().unwrap()?.to_string(), "Nb8-c6");
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoints {
+    interval: usize,
+    // Entry `i` is the state after `(i + 1) * interval` plies.
+    entries: Vec<Checkpoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checkpoint {
+    bit_offset: usize,
+    position: PackedSetup,
+}
+
+impl Checkpoints {
+    /// Builds the checkpoint table for `encoded`, with a checkpoint every `interval` plies.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is 0.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the game contains invalid moves.
+    pub fn new(encoded: &EncodedGame, interval: usize) -> DecodeResult<Self> {
+        assert!(interval > 0, "checkpoint interval must be positive");
+
+        let mut entries = vec![];
+        let mut decoder = MoveByMoveDecoder::new(encoded);
+        while decoder.skip(interval)? == interval {
+            let setup = decoder.position().to_setup(EnPassantMode::Always);
+            entries.push(Checkpoint {
+                bit_offset: decoder.bit_offset,
+                position: PackedSetup::pack_standard(&setup)
+                    .expect("positions of a decoded game can be packed"),
+            });
+        }
+
+        Ok(Self { interval, entries })
+    }
+
+    /// The number of plies between two checkpoints.
+    #[must_use]
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// The number of checkpoints in the table.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the table has no checkpoints, that is, whether the game is shorter than one interval.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the last checkpoint at or before `ply`, as the ply of the checkpoint,
+    /// the bit offset of the next move, and the position. `None` if there is no such checkpoint
+    /// (or if it cannot be unpacked, which only happens for a corrupted table).
+    pub(crate) fn at_or_before(&self, ply: usize) -> Option<(usize, usize, Chess)> {
+        let index = (ply / self.interval)
+            .min(self.entries.len())
+            .checked_sub(1)?;
+        let entry = &self.entries[index];
+        let setup = entry.position.unpack_standard().ok()?;
+        let pos = Chess::from_setup(setup, CastlingMode::Standard).ok()?;
+        Some(((index + 1) * self.interval, entry.bit_offset, pos))
+    }
+
+    /// Converts the checkpoint table to a byte vector. Use `from_bytes` to convert the result
+    /// back to a [`Checkpoints`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wrt = vec![];
+        wrt.write_u32::<LittleEndian>(to_u32(self.interval))
+            .unwrap();
+        wrt.write_u32::<LittleEndian>(to_u32(self.entries.len()))
+            .unwrap();
+        for entry in &self.entries {
+            let packed = entry.position.as_bytes();
+            wrt.write_u32::<LittleEndian>(to_u32(entry.bit_offset))
+                .unwrap();
+            #[allow(clippy::cast_possible_truncation)]
+            wrt.push(packed.len() as u8);
+            wrt.extend_from_slice(packed);
+        }
+        wrt
+    }
+
+    /// Converts a byte vector (that was the output of `to_bytes`) to a [`Checkpoints`].
+    /// Returns `None` if the bytes are not a valid checkpoint table.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut rdr = Cursor::new(bytes);
+        let interval = rdr.read_u32::<LittleEndian>().ok()? as usize;
+        let len = rdr.read_u32::<LittleEndian>().ok()? as usize;
+        if interval == 0 {
+            return None;
+        }
+
+        let mut entries = Vec::with_capacity(len.min(bytes.len()));
+        for _ in 0..len {
+            let bit_offset = rdr.read_u32::<LittleEndian>().ok()? as usize;
+            let mut packed = vec![0; usize::from(rdr.read_u8().ok()?)];
+            rdr.read_exact(&mut packed).ok()?;
+            entries.push(Checkpoint {
+                bit_offset,
+                position: PackedSetup::try_from_bytes(&packed).ok()?,
+            });
+        }
+
+        Some(Self { interval, entries })
+    }
+}
+
+fn to_u32(n: usize) -> u32 {
+    u32::try_from(n).expect("checkpoint tables are limited to 2^32 plies and bits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_game, encode_pgn};
+
+    const PGN: &str = "1. Nf3 c5 2. g3 Nc6 3. Bg2 e5 4. O-O e4 5. Ne1 f5 6. d3 d5 7. dxe4 fxe4
+        8. Bf4 Nf6 9. e3 Be7 10. Nd2 Bg4 11. c3 Bxd1 12. Rxd1 d4 13. Nc2 d3 14. Na3 O-O";
+
+    #[test]
+    fn seek_with_checkpoints() {
+        let encoded = encode_pgn(PGN).unwrap();
+        let (moves, positions) = decode_game(&encoded).unwrap();
+        let checkpoints = Checkpoints::new(&encoded, 5).unwrap();
+        assert_eq!(checkpoints.len(), 5);
+
+        let mut decoder = MoveByMoveDecoder::new(&encoded);
+        for ply in [0, 4, 5, 6, 27, 12, 10, 28, 1] {
+            assert!(
+                decoder
+                    .seek_to_ply_with_checkpoints(ply, &checkpoints)
+                    .unwrap()
+            );
+            assert_eq!(decoder.ply(), ply);
+            if ply > 0 {
+                assert_eq!(decoder.position(), &positions[ply - 1]);
+            }
+            if ply < moves.len() {
+                assert_eq!(decoder.next_move().unwrap().unwrap(), moves[ply]);
+            }
+        }
+        assert!(
+            !decoder
+                .seek_to_ply_with_checkpoints(29, &checkpoints)
+                .unwrap()
+        );
+        assert_eq!(decoder.ply(), 28);
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let encoded = encode_pgn(PGN).unwrap();
+        let checkpoints = Checkpoints::new(&encoded, 3).unwrap();
+        let bytes = checkpoints.to_bytes();
+        assert_eq!(Checkpoints::from_bytes(&bytes), Some(checkpoints));
+        assert_eq!(Checkpoints::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(Checkpoints::from_bytes(&[0; 8]), None);
+    }
+}
@@ -1,8 +1,13 @@
 use bitm::BitAccess;
-use minimum_redundancy::{BitsPerFragment, Code, Coding, Decoder};
+use minimum_redundancy::{BitsPerFragment, Code, Coding};
 use std::sync::LazyLock;
 
-use crate::EncodedGame;
+use crate::{EncodedGame, GameDecodeErrorKind};
+
+// The number of bits that are resolved by one lookup in the decoding table.
+// Codes of up to this length (which are almost all codes in practice) are decoded
+// with a single lookup.
+const LOOKUP_BITS: u32 = 10;
 
 fn generate_code_from_lichess_weights() -> Coding<u8> {
     Coding::from_frequencies(BitsPerFragment(1), WEIGHTS)
@@ -12,7 +17,7 @@ fn generate_code_from_lichess_weights() -> Coding<u8> {
 // https://github.com/lichess-org/compression/blob/master/src/main/java/game/Huffman.java#L64
 // They are modified so each value has a unique weight.
 #[allow(clippy::unreadable_literal)]
-const WEIGHTS: [u64; 256] = [
+pub const WEIGHTS: [u64; 256] = [
     4291794708, 2564166394, 1691784111, 1318338522, 1083775010, 854516621, 694395945, 600873480,
     540222668, 504269367, 465212587, 438102646, 447170168, 389166683, 388553268, 348005083,
     327081827, 322330459, 314070532, 292020690, 269390360, 271238566, 253712814, 243762438,
@@ -35,19 +40,86 @@ const WEIGHTS: [u64; 256] = [
 static CODE_FROM_LICHESS_WEIGHTS: LazyLock<Coding<u8>> =
     LazyLock::new(generate_code_from_lichess_weights);
 
-pub static BOOK_FROM_LICHESS_WEIGHTS: LazyLock<Book> = LazyLock::new(|| Book {
-    codes: (*CODE_FROM_LICHESS_WEIGHTS).reversed_codes_for_values_array(),
-});
-
-pub fn get_decoder<'a>() -> Decoder<'a, u8> {
-    (*CODE_FROM_LICHESS_WEIGHTS).decoder()
-}
+pub static BOOK_FROM_LICHESS_WEIGHTS: LazyLock<Book> =
+    LazyLock::new(|| Book::new(&CODE_FROM_LICHESS_WEIGHTS));
 
 pub struct Book {
     codes: [Code; 256],
+    // Indexed by the next `LOOKUP_BITS` bits of the stream (first bit in the lowest bit).
+    // An entry holds `(len << 8) | symbol`, or 0 if the code is longer than `LOOKUP_BITS`.
+    table: Box<[u16]>,
+    // The codes that are longer than `LOOKUP_BITS`, as `(code, symbol)`.
+    long_codes: Vec<(Code, u8)>,
 }
 
 impl Book {
+    fn new(coding: &Coding<u8>) -> Self {
+        let codes = coding.reversed_codes_for_values_array();
+        let mut table = vec![0; 1 << LOOKUP_BITS].into_boxed_slice();
+        let mut long_codes = vec![];
+        for (symbol, code) in (0..=u8::MAX).zip(codes) {
+            if code.len > LOOKUP_BITS {
+                long_codes.push((code, symbol));
+                continue;
+            }
+            // Fill every entry whose low `code.len` bits are the code.
+            #[allow(clippy::cast_possible_truncation)]
+            let entry = ((code.len as u16) << 8) | u16::from(symbol);
+            for high in 0..1_u32 << (LOOKUP_BITS - code.len) {
+                table[((high << code.len) | code.content) as usize] = entry;
+            }
+        }
+        long_codes.sort_unstable_by_key(|(code, _)| code.len);
+        Self {
+            codes,
+            table,
+            long_codes,
+        }
+    }
+
+    /// Decodes the symbol whose code starts at bit `bit_offset` of `source`, without reading
+    /// at or past `bit_end`. Returns the symbol and the length of its code, or `None` if
+    /// `bit_offset` is at the end of the stream.
+    pub fn decode(
+        &self,
+        source: BitSource<'_>,
+        bit_offset: usize,
+        bit_end: usize,
+    ) -> Option<Result<(u8, u32), GameDecodeErrorKind>> {
+        if bit_offset >= bit_end {
+            return None;
+        }
+        let available = bit_end - bit_offset;
+        let bits = source.peek(bit_offset);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let entry = self.table[(bits & ((1 << LOOKUP_BITS) - 1)) as usize];
+        let (symbol, len) = if entry == 0 {
+            let Some(&(code, symbol)) = self
+                .long_codes
+                .iter()
+                .find(|(code, _)| bits & ((1 << code.len) - 1) == u64::from(code.content))
+            else {
+                return Some(Err(GameDecodeErrorKind::InvalidCode));
+            };
+            (symbol, code.len)
+        } else {
+            #[allow(clippy::cast_possible_truncation)]
+            (entry as u8, u32::from(entry >> 8))
+        };
+
+        if len as usize > available {
+            Some(Err(GameDecodeErrorKind::IncompleteCode))
+        } else {
+            Some(Ok((symbol, len)))
+        }
+    }
+
+    /// The length of the code of `symbol`.
+    pub fn code_len(&self, symbol: u8) -> u32 {
+        self.codes[symbol as usize].len
+    }
+
     pub fn encode(&self, buffer: &mut EncodedGame, symbol: u8) {
         let code = &self.codes[symbol as usize];
         let bit_len = buffer.inner.len() * 64;
@@ -63,9 +135,62 @@ impl Book {
     }
 }
 
+/// Appends the lowest `len` (at most 32) bits of `bits` to `buffer`.
+pub fn push_bits(buffer: &mut EncodedGame, bits: u64, len: u32) {
+    debug_assert!(len <= 32);
+    if len == 0 {
+        return;
+    }
+    if buffer.bit_index + len as usize > buffer.inner.len() * 64 {
+        buffer.inner.push(0);
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    buffer
+        .inner
+        .init_bits(buffer.bit_index, bits & ((1 << len) - 1), len as u8);
+    buffer.bit_index += len as usize;
+}
+
+/// The bits of an encoded game, either as the words of an [`EncodedGame`] or as the bytes
+/// of its `to_bytes` format, which can be read without copying them into words.
+#[derive(Debug, Clone, Copy)]
+pub enum BitSource<'a> {
+    Words(&'a [u64]),
+    Bytes(&'a [u8]),
+}
+
+impl BitSource<'_> {
+    // Returns the (at least 56) bits starting at bit `bit_offset`, with the first bit in the
+    // lowest bit. Bits past the end of the source are zero.
+    pub fn peek(self, bit_offset: usize) -> u64 {
+        match self {
+            BitSource::Words(words) => {
+                let index = bit_offset / 64;
+                let shift = bit_offset % 64;
+                let low = words.get(index).map_or(0, |w| w >> shift);
+                if shift == 0 {
+                    low
+                } else {
+                    low | words.get(index + 1).map_or(0, |w| w << (64 - shift))
+                }
+            }
+            BitSource::Bytes(bytes) => {
+                let mut buf = [0; 8];
+                let rest = bytes.get(bit_offset / 8..).unwrap_or_default();
+                let n = rest.len().min(8);
+                buf[..n].copy_from_slice(&rest[..n]);
+                u64::from_le_bytes(buf) >> (bit_offset % 8)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::codes::WEIGHTS;
+    use super::{BOOK_FROM_LICHESS_WEIGHTS, BitSource, CODE_FROM_LICHESS_WEIGHTS, WEIGHTS};
+    use crate::{EncodedGame, GameDecodeErrorKind};
+    use bitm::BitAccess;
+    use minimum_redundancy::DecodingResult;
 
     #[test]
     fn deterministic_code_gen() {
@@ -83,4 +208,76 @@ mod tests {
         unique_weights.dedup();
         assert_eq!(WEIGHTS.len(), unique_weights.len());
     }
+
+    #[test]
+    fn table_decoding_matches_canonical_decoder() {
+        let book = &*BOOK_FROM_LICHESS_WEIGHTS;
+        assert!(!book.long_codes.is_empty());
+
+        // Every symbol, at many offsets within a word, so codes cross word boundaries.
+        // Codes longer than 32 bits are skipped: `encode` cannot write them, but they belong
+        // to ranks above the maximum number of legal moves in a position.
+        let mut encoded = EncodedGame {
+            inner: vec![],
+            bit_index: 0,
+        };
+        let symbols: Vec<u8> = (0..=u8::MAX)
+            .filter(|&symbol| book.codes[usize::from(symbol)].len <= 32)
+            .chain((0..64).map(|i| i % 3))
+            .collect();
+        assert!(symbols.contains(&218));
+        for &symbol in &symbols {
+            book.encode(&mut encoded, symbol);
+        }
+
+        let bytes = encoded.to_bytes();
+        let mut huff_decoder = CODE_FROM_LICHESS_WEIGHTS.decoder();
+        let mut bit_iter = encoded.inner.bit_in_range_iter(0..encoded.bit_index);
+        let mut bit_offset = 0;
+        for &symbol in &symbols {
+            let DecodingResult::Value(&expected) = huff_decoder.decode(&mut bit_iter) else {
+                panic!("canonical decoder failed");
+            };
+            huff_decoder.reset();
+            let (decoded, len) = book
+                .decode(
+                    BitSource::Words(&encoded.inner),
+                    bit_offset,
+                    encoded.bit_index,
+                )
+                .unwrap()
+                .unwrap();
+            assert_eq!(decoded, symbol);
+            assert_eq!(decoded, expected);
+            let from_bytes = book
+                .decode(BitSource::Bytes(&bytes), bit_offset, encoded.bit_index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(from_bytes, (decoded, len));
+            bit_offset += len as usize;
+        }
+        assert_eq!(bit_offset, encoded.bit_index);
+        assert!(
+            book.decode(
+                BitSource::Words(&encoded.inner),
+                bit_offset,
+                encoded.bit_index
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn table_decoding_incomplete() {
+        let book = &*BOOK_FROM_LICHESS_WEIGHTS;
+        let mut encoded = EncodedGame {
+            inner: vec![],
+            bit_index: 0,
+        };
+        book.encode(&mut encoded, 200);
+        assert_eq!(
+            book.decode(BitSource::Words(&encoded.inner), 0, encoded.bit_index - 1),
+            Some(Err(GameDecodeErrorKind::IncompleteCode))
+        );
+    }
 }
@@ -0,0 +1,207 @@
+use crate::{EncodedGame, PgnGame};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Removes duplicate games, such as the same game from several sources, merging their tags.
+///
+/// Two games are duplicates if they have the same moves. Since the encoding of a move
+/// sequence is canonical, this only compares the encoded bits, and ignores everything
+/// else: unused words, the padding bits after the last move and the metadata bits of the
+/// format of [`EncodedGame::to_bytes`].
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn_games, GameDeduplicator};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let pgn = r#"[Event "Casual game"]
+/// [White "Anderssen"]
+///
+/// 1. e4 e5 2. f4 exf4 *
+///
+/// [Event "King's Gambit"]
+///
+/// 1. d4 d5 *
+///
+/// [Event "Casual game"]
+/// [Site "London"]
+///
+/// 1. e4 e5 2. f4 exf4 *
+/// "#;
+///
+/// let mut dedup = GameDeduplicator::new();
+/// for game in encode_pgn_games(pgn.as_bytes()) {
+///     dedup.add(game?);
+/// }
+/// assert_eq!(dedup.len(), 2);
+/// assert_eq!(dedup.duplicates(), 1);
+/// assert_eq!(
+///     dedup.games()[0].tags,
+///     [
+///         ("Event".to_string(), "Casual game".to_string()),
+///         ("White".to_string(), "Anderssen".to_string()),
+///         ("Site".to_string(), "London".to_string()),
+///     ]
+/// );
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GameDeduplicator {
+    ids: HashMap<Vec<u8>, usize>,
+    games: Vec<PgnGame>,
+    duplicates: usize,
+}
+
+impl GameDeduplicator {
+    /// Creates a deduplicator without games.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a game and returns the index of its unique copy in [`GameDeduplicator::games`].
+    ///
+    /// If a game with the same moves was added before, the game is not added again. Instead,
+    /// its tag pairs that the earlier copy does not have yet are appended to the tags of the
+    /// earlier copy. Tags with the same name but different values are all kept, so conflicts
+    /// between the sources are not lost.
+    pub fn add(&mut self, game: PgnGame) -> usize {
+        match self.ids.entry(move_key(&game.encoded)) {
+            Entry::Occupied(entry) => {
+                let id = *entry.get();
+                let tags = &mut self.games[id].tags;
+                for tag in game.tags {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+                self.duplicates += 1;
+                id
+            }
+            Entry::Vacant(entry) => {
+                let id = self.games.len();
+                entry.insert(id);
+                self.games.push(game);
+                id
+            }
+        }
+    }
+
+    /// The unique games, in the order they were first added. The byte offset of a game is
+    /// the one of its first copy.
+    #[must_use]
+    pub fn games(&self) -> &[PgnGame] {
+        &self.games
+    }
+
+    /// Returns the unique games, in the order they were first added.
+    #[must_use]
+    pub fn into_games(self) -> Vec<PgnGame> {
+        self.games
+    }
+
+    /// The number of unique games.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    /// Whether no games have been added.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// The number of added games that were duplicates of earlier games.
+    #[must_use]
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+}
+
+// Returns the bytes of the moves of a game, which are equal for two games if and only if
+// they have the same moves.
+pub(crate) fn move_key(encoded: &EncodedGame) -> Vec<u8> {
+    let mut bytes = encoded.to_bytes();
+    // `to_bytes` keeps whatever bits follow the last move in its last byte.
+    let used_bits = encoded.bit_index % 8;
+    if used_bits != 0 {
+        let last = bytes.len() - 2;
+        bytes[last] &= (1 << used_bits) - 1;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_pgn_games;
+
+    const PGN: &str = r#"[Event "A"]
+[Result "1-0"]
+
+1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[Event "B"]
+
+1. e4 e5 *
+
+[Event "A"]
+[Result "1-0"]
+
+1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+
+[Event "C"]
+[Result "1-0"]
+
+1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0
+"#;
+
+    #[test]
+    fn duplicates_are_merged() {
+        let mut dedup = GameDeduplicator::new();
+        let ids: Vec<usize> = encode_pgn_games(PGN.as_bytes())
+            .map(|game| dedup.add(game.unwrap()))
+            .collect();
+        assert_eq!(ids, [0, 1, 0, 0]);
+        assert_eq!(dedup.len(), 2);
+        assert_eq!(dedup.duplicates(), 2);
+
+        let games = dedup.into_games();
+        assert_eq!(games[0].byte_offset, 0);
+        let tags: Vec<String> = games[0]
+            .tags
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        assert_eq!(tags, ["Event=A", "Result=1-0", "Event=C"]);
+    }
+
+    #[test]
+    fn metadata_and_padding_bits_are_ignored() {
+        let game = encode_pgn_games("1. e4 e5 2. Nf3".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_ne!(game.encoded.bit_index % 8, 0);
+
+        let mut bytes = game.encoded.to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] |= 0b1100_0000;
+        bytes[last - 1] |= 0b1000_0000;
+        let with_metadata = EncodedGame::from_bytes(&bytes);
+        assert_ne!(with_metadata, game.encoded);
+        assert_eq!(move_key(&with_metadata), move_key(&game.encoded));
+
+        let mut dedup = GameDeduplicator::new();
+        dedup.add(game.clone());
+        let copy = PgnGame {
+            encoded: with_metadata,
+            ..game
+        };
+        assert_eq!(dedup.add(copy), 0);
+        assert_eq!(dedup.len(), 1);
+    }
+}
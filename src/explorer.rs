@@ -0,0 +1,330 @@
+use crate::{DecodeResult, EncodedGame, MoveByMoveDecoder, PgnGame};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, Color, EnPassantMode, KnownOutcome, Move, Outcome};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+/// Statistics of the games in which a move was played (or a position was reached).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MoveStats {
+    /// The number of games.
+    pub games: u64,
+    /// The number of games that White won.
+    pub white: u64,
+    /// The number of games that were drawn.
+    pub draws: u64,
+    /// The number of games that Black won.
+    pub black: u64,
+    /// The sum of the average ratings of the games with known ratings.
+    pub rating_sum: u64,
+    /// The number of games with known ratings.
+    pub rated_games: u64,
+}
+
+impl MoveStats {
+    /// The average rating of the players of the games with known ratings, if any.
+    #[must_use]
+    pub fn average_rating(&self) -> Option<u64> {
+        self.rating_sum.checked_div(self.rated_games)
+    }
+
+    fn add(&mut self, outcome: Outcome, rating: Option<u32>) {
+        self.games += 1;
+        match outcome {
+            Outcome::Known(KnownOutcome::Decisive {
+                winner: Color::White,
+            }) => self.white += 1,
+            Outcome::Known(KnownOutcome::Decisive {
+                winner: Color::Black,
+            }) => self.black += 1,
+            Outcome::Known(KnownOutcome::Draw) => self.draws += 1,
+            Outcome::Unknown => {}
+        }
+        if let Some(rating) = rating {
+            self.rating_sum += u64::from(rating);
+            self.rated_games += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &MoveStats) {
+        self.games += other.games;
+        self.white += other.white;
+        self.draws += other.draws;
+        self.black += other.black;
+        self.rating_sum += other.rating_sum;
+        self.rated_games += other.rated_games;
+    }
+}
+
+/// An opening tree of encoded games: for every position in the first moves of the games,
+/// which moves were played and with which results.
+///
+/// Positions are identified by their Zobrist hashes, so transpositions are merged, like in
+/// the Lichess opening explorer.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, OpeningExplorer};
+/// use shakmaty::{Chess, Outcome};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut explorer = OpeningExplorer::new(10);
+/// explorer.add_game(&encode_pgn("1. e4 e5 2. Nf3")?, "1-0".parse()?, Some(2000))?;
+/// explorer.add_game(&encode_pgn("1. e4 c5")?, "1/2-1/2".parse()?, None)?;
+/// explorer.add_game(&encode_pgn("1. d4 d5")?, Outcome::Unknown, Some(1500))?;
+///
+/// let moves = explorer.query(&Chess::default());
+/// assert_eq!(moves[0].0.to_string(), "e2-e4");
+/// assert_eq!(moves[0].1.games, 2);
+/// assert_eq!(moves[0].1.white, 1);
+/// assert_eq!(moves[0].1.draws, 1);
+/// assert_eq!(moves[0].1.average_rating(), Some(2000));
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpeningExplorer {
+    max_plies: usize,
+    positions: HashMap<u64, HashMap<Move, MoveStats>>,
+}
+
+impl OpeningExplorer {
+    /// Creates an empty opening tree, that records the first `max_plies` moves of each game.
+    #[must_use]
+    pub fn new(max_plies: usize) -> Self {
+        Self {
+            max_plies,
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Adds the first moves of a game, with its outcome and the average rating of
+    /// its players, if known.
+    ///
+    /// A position that occurs more than once in the game is only counted once, with the
+    /// move that was played the first time.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the first moves of the game are invalid. The game is
+    /// then not added at all.
+    pub fn add_game(
+        &mut self,
+        encoded: &EncodedGame,
+        outcome: Outcome,
+        rating: Option<u32>,
+    ) -> DecodeResult<()> {
+        let mut moves = Vec::with_capacity(self.max_plies);
+        let mut seen = HashSet::with_capacity(self.max_plies);
+        let mut decoder = MoveByMoveDecoder::new(encoded);
+        while decoder.ply() < self.max_plies {
+            let hash = position_hash(decoder.position());
+            match decoder.next_move() {
+                Some(m) => {
+                    let m = m?;
+                    if seen.insert(hash) {
+                        moves.push((hash, m));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        for (hash, m) in moves {
+            self.positions
+                .entry(hash)
+                .or_default()
+                .entry(m)
+                .or_default()
+                .add(outcome, rating);
+        }
+        Ok(())
+    }
+
+    /// Adds the first moves of a game that was read by [`crate::encode_pgn_games`], with the
+    /// outcome from its `Result` tag and the average of its `WhiteElo` and `BlackElo` tags.
+    /// The game only counts as rated if both tags are numbers from 0 to [`u32::MAX`].
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the first moves of the game are invalid. The game is
+    /// then not added at all.
+    pub fn add_pgn_game(&mut self, game: &PgnGame) -> DecodeResult<()> {
+        let tag = |name: &str| {
+            game.tags
+                .iter()
+                .find(|(tag, _)| tag == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let outcome = tag("Result")
+            .and_then(|result| result.parse().ok())
+            .unwrap_or(Outcome::Unknown);
+        let rating = |name| tag(name)?.parse::<u32>().ok().map(u64::from);
+        // The average of two `u32` ratings fits in a `u32` again.
+        let rating = rating("WhiteElo")
+            .zip(rating("BlackElo"))
+            .and_then(|(white, black)| u32::try_from((white + black) / 2).ok());
+        self.add_game(&game.encoded, outcome, rating)
+    }
+
+    /// Returns the moves that were played in `pos`, with their statistics, the most
+    /// played move first.
+    #[must_use]
+    pub fn query(&self, pos: &Chess) -> Vec<(Move, MoveStats)> {
+        let mut moves: Vec<(Move, MoveStats)> = self
+            .positions
+            .get(&position_hash(pos))
+            .map(|moves| moves.iter().map(|(&m, &stats)| (m, stats)).collect())
+            .unwrap_or_default();
+        moves.sort_by_cached_key(|(m, stats)| (Reverse(stats.games), m.to_string()));
+        moves
+    }
+
+    /// Returns the statistics of all games in which a move was played in `pos`.
+    #[must_use]
+    pub fn position_stats(&self, pos: &Chess) -> MoveStats {
+        let mut total = MoveStats::default();
+        for (_, stats) in self.query(pos) {
+            total.merge(&stats);
+        }
+        total
+    }
+
+    /// The number of distinct positions in the tree.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether the tree contains no positions.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+fn position_hash(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_pgn_games;
+    use shakmaty::{Position, Square};
+
+    const PGN: &str = r#"[Result "1-0"]
+[WhiteElo "2100"]
+[BlackElo "1900"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0
+
+[Result "0-1"]
+[WhiteElo "1500"]
+
+1. Nf3 Nc6 2. e4 e5 3. Bc4 Bc5 0-1
+
+[Result "1/2-1/2"]
+
+1. e4 c5 2. Nf3 d6 1/2-1/2
+"#;
+
+    #[test]
+    fn explorer_from_pgn() {
+        let mut explorer = OpeningExplorer::new(4);
+        for game in encode_pgn_games(PGN.as_bytes()) {
+            explorer.add_pgn_game(&game.unwrap()).unwrap();
+        }
+
+        let root = explorer.query(&Chess::default());
+        assert_eq!(root.len(), 2);
+        let (e4, e4_stats) = root[0];
+        assert_eq!(e4.to(), Square::E4);
+        assert_eq!(
+            e4_stats,
+            MoveStats {
+                games: 2,
+                white: 1,
+                draws: 1,
+                black: 0,
+                rating_sum: 2000,
+                rated_games: 1,
+            }
+        );
+        assert_eq!(root[1].1.black, 1);
+        // Only White's rating is known, so the game is not rated.
+        assert_eq!(root[1].1.average_rating(), None);
+        assert_eq!(explorer.position_stats(&Chess::default()).games, 3);
+
+        // The first two games transpose to the same position after 4 plies, but the moves
+        // played there are beyond the depth limit.
+        let mut pos = Chess::default();
+        for (from, to) in [
+            (Square::E2, Square::E4),
+            (Square::E7, Square::E5),
+            (Square::G1, Square::F3),
+            (Square::B8, Square::C6),
+        ] {
+            let m = pos
+                .legal_moves()
+                .into_iter()
+                .find(|m| m.from() == Some(from) && m.to() == to)
+                .unwrap();
+            pos.play_unchecked(m);
+        }
+        assert!(explorer.query(&pos).is_empty());
+
+        let mut after_e4 = Chess::default();
+        after_e4.play_unchecked(e4);
+        let replies = explorer.query(&after_e4);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies.iter().map(|(_, stats)| stats.games).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn repeated_positions_count_once() {
+        let mut explorer = OpeningExplorer::new(20);
+        let pgn = "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. e4";
+        let encoded = crate::encode_pgn(pgn).unwrap();
+        explorer.add_game(&encoded, Outcome::Unknown, None).unwrap();
+
+        let root = explorer.query(&Chess::default());
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].0.to(), Square::F3);
+        assert_eq!(root[0].1.games, 1);
+        assert_eq!(explorer.position_stats(&Chess::default()).games, 1);
+    }
+
+    #[test]
+    fn large_ratings_do_not_overflow() {
+        let pgn = r#"[WhiteElo "4294967295"]
+[BlackElo "4294967293"]
+
+1. e4 *
+
+[WhiteElo "99999999999"]
+[BlackElo "1800"]
+
+1. e4 *
+"#;
+        let mut explorer = OpeningExplorer::new(2);
+        for game in encode_pgn_games(pgn.as_bytes()) {
+            explorer.add_pgn_game(&game.unwrap()).unwrap();
+        }
+        let stats = explorer.position_stats(&Chess::default());
+        // White's rating in the second game is out of range, so it is not rated.
+        assert_eq!(stats.rated_games, 1);
+        assert_eq!(stats.rating_sum, u64::from(u32::MAX - 1));
+    }
+
+    #[test]
+    fn invalid_games_are_not_added() {
+        let mut explorer = OpeningExplorer::new(10);
+        let mut encoded = crate::encode_pgn("1. e4 e5").unwrap();
+        crate::test_util::corrupt(&mut encoded);
+        assert!(explorer.add_game(&encoded, Outcome::Unknown, None).is_err());
+        assert!(explorer.is_empty());
+    }
+}
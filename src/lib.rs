@@ -1,18 +1,50 @@
 #![crate_name = "chess_huffman"]
 
+mod archive;
+mod archive_file;
+#[cfg(feature = "rayon")]
+mod batch;
+mod checkpoints;
 mod codes;
+mod dedup;
+mod explorer;
+#[cfg(feature = "mmap")]
+mod mmap;
 mod pgn;
+mod position_index;
 mod psqt;
+mod query;
 mod ranking;
+mod search;
+mod stats;
+#[cfg(test)]
+mod test_util;
 #[cfg(test)]
 mod tests;
+mod trie;
+
+pub use archive::{GameArchive, GameArchiveWriter};
+pub use archive_file::GameArchiveFile;
+#[cfg(feature = "rayon")]
+pub use batch::{decode_game_batch, encode_game_batch, encode_pgn_batch};
+pub use checkpoints::Checkpoints;
+pub use dedup::GameDeduplicator;
+pub use explorer::{MoveStats, OpeningExplorer};
+#[cfg(feature = "mmap")]
+pub use mmap::MmapGameArchive;
+pub use position_index::{PositionIndex, PositionIndexBuilder};
+pub use query::Query;
+pub use search::{PositionSearch, SearchError, SearchHit, SearchResult};
+pub use stats::{CompressionStats, GameCost, GamePhase, PhaseStats, RankFrequency};
+pub use trie::{GameTrie, TrieStats};
 
-use bitm::BitAccess;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use codes::Book;
-use minimum_redundancy::{Decoder, DecodingResult};
-use shakmaty::san::{ParseSanError, SanError};
-use shakmaty::{Chess, Move, PlayError, Position};
+use codes::{BitSource, Book};
+use shakmaty::fen::Fen;
+use shakmaty::san::{ParseSanError, San, SanError, SanPlus, Suffix};
+use shakmaty::uci::{IllegalUciMoveError, ParseUciMoveError, UciMove};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{CastlingMode, Chess, EnPassantMode, Move, PlayError, Position};
 use std::fmt;
 use std::io::Cursor;
 
@@ -20,18 +52,43 @@ use std::io::Cursor;
 pub type EncodeResult<T> = Result<T, GameEncodeError>;
 /// The result of a decoding operation.
 pub type DecodeResult<T> = Result<T, GameDecodeError>;
+/// The result of a lenient encoding operation: the encoded game, containing all moves
+/// up to the first move that could not be encoded, and the error for that move, if any.
+pub type LenientEncodeResult = (EncodedGame, Option<GameEncodeError>);
+/// The result of a lossy decoding operation: the moves and positions up to the first move
+/// that could not be decoded, and the error for that move, if any.
+pub type LossyDecodeResult = (Vec<Move>, Vec<Chess>, Option<GameDecodeError>);
 
 /// Error when encoding a chess game.
+///
+/// More fields may be added in the future, so the error cannot be constructed or
+/// destructured exhaustively outside of this crate.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct GameEncodeError {
     /// The underlying problem that caused the error.
     pub kind: GameEncodeErrorKind,
     /// A textual explanation for the error.
     pub explanation: String,
+    /// The index of the ply (half-move, starting from 0) that could not be encoded,
+    /// if the error is caused by a specific move.
+    pub ply: Option<usize>,
+    /// The SAN of the move that could not be encoded, if the move was given as SAN.
+    pub san: Option<Box<str>>,
+    /// The UCI of the move that could not be encoded, if the move was given as UCI.
+    pub uci: Option<Box<str>>,
+    /// The FEN of the position in which the move could not be encoded,
+    /// if the error is caused by a specific move.
+    pub fen: Option<String>,
+    /// For PGN input: the byte offset in the input where the game that could not be encoded starts.
+    pub byte_offset: Option<u64>,
 }
 
 /// Kind of error when encoding a chess game.
+///
+/// More kinds may be added in the future, so matches on it need a wildcard arm.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum GameEncodeErrorKind {
     /// An I/O error.
     IoError,
@@ -41,60 +98,210 @@ pub enum GameEncodeErrorKind {
     SanError,
     /// An invalid SAN so it could not be parsed.
     ParseSanError,
+    /// An illegal UCI (= Universal Chess Interface) move.
+    UciError,
+    /// An invalid UCI move so it could not be parsed.
+    ParseUciError,
     /// An illegal move in the sequence of moves to be encoded.
     IllegalMove,
 }
 
+impl GameEncodeError {
+    fn new(kind: GameEncodeErrorKind, explanation: String) -> Self {
+        Self {
+            kind,
+            explanation,
+            ply: None,
+            san: None,
+            uci: None,
+            fen: None,
+            byte_offset: None,
+        }
+    }
+
+    /// Records the position in which a move could not be encoded, unless it is already known.
+    fn at_position(mut self, pos: &Chess) -> Self {
+        if self.ply.is_none() {
+            self.ply = Some(ply_index(pos));
+            self.fen = Some(Fen::from_position(pos, EnPassantMode::Legal).to_string());
+        }
+        self
+    }
+}
+
 impl std::error::Error for GameEncodeError {}
 
 impl fmt::Display for GameEncodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Failed to encode chess game: {}", &self.explanation)
+        write!(f, "Failed to encode chess game: {}", &self.explanation)?;
+        if let Some(san) = &self.san {
+            write!(f, " (move {san})")?;
+        }
+        if let Some(uci) = &self.uci {
+            write!(f, " (move {uci})")?;
+        }
+        if let Some(ply) = self.ply {
+            write!(f, " at ply {ply}")?;
+        }
+        if let Some(fen) = &self.fen {
+            write!(f, " in position {fen}")?;
+        }
+        if let Some(byte_offset) = self.byte_offset {
+            write!(f, ", in the game starting at byte {byte_offset}")?;
+        }
+        Ok(())
     }
 }
 
 /// Error when decoding an encoded bit vector into a game, because the bit vector is invalid.
+///
+/// More fields may be added in the future, so the error cannot be constructed or
+/// destructured exhaustively outside of this crate.
 #[derive(Debug)]
-pub struct GameDecodeError {}
+#[non_exhaustive]
+pub struct GameDecodeError {
+    /// The underlying problem that caused the error.
+    pub kind: GameDecodeErrorKind,
+    /// The index of the ply (half-move, starting from 0) that could not be decoded.
+    pub ply: usize,
+    /// The offset in the bit vector where the code of the ply that could not be decoded starts,
+    /// if the error was found in a bit vector.
+    pub bit_offset: Option<usize>,
+    /// The last position that was decoded successfully.
+    pub position: Box<Chess>,
+}
+
+/// Kind of error when decoding a chess game.
+///
+/// More kinds may be added in the future, so matches on it need a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GameDecodeErrorKind {
+    /// The bit vector ends in the middle of a Huffman code.
+    IncompleteCode,
+    /// The bits do not form a valid Huffman code.
+    InvalidCode,
+    /// A decoded move rank is not lower than the number of legal moves in the position.
+    RankOutOfRange,
+    /// An illegal move was played on a decoded position.
+    IllegalMove,
+    /// A bit after the end of the game is set (only checked by strict decoding).
+    NonZeroPadding,
+    /// The length of a byte vector does not match the padding it claims to have
+    /// (only checked by strict decoding).
+    InvalidLength,
+}
+
+impl GameDecodeError {
+    fn new(kind: GameDecodeErrorKind, ply: usize, bit_offset: usize, position: &Chess) -> Self {
+        Self {
+            kind,
+            ply,
+            bit_offset: Some(bit_offset),
+            position: Box::new(position.clone()),
+        }
+    }
+}
 
 impl std::error::Error for GameDecodeError {}
 
 impl fmt::Display for GameDecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Cannot decode invalid bit vector")
+        let reason = match self.kind {
+            GameDecodeErrorKind::IncompleteCode => "incomplete code",
+            GameDecodeErrorKind::InvalidCode => "invalid code",
+            GameDecodeErrorKind::RankOutOfRange => "move rank out of range",
+            GameDecodeErrorKind::IllegalMove => "illegal move",
+            GameDecodeErrorKind::NonZeroPadding => "non-zero padding",
+            GameDecodeErrorKind::InvalidLength => "invalid length",
+        };
+        write!(
+            f,
+            "Cannot decode invalid bit vector: {reason} at ply {}",
+            self.ply
+        )?;
+        if let Some(bit_offset) = self.bit_offset {
+            write!(f, " (bit {bit_offset})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error when a game in a collection of games cannot be decoded.
+///
+/// More fields may be added in the future, so the error cannot be constructed or
+/// destructured exhaustively outside of this crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct GameError {
+    /// The index of the game in the collection.
+    pub game: usize,
+    /// Why the game could not be decoded.
+    pub error: GameDecodeError,
+}
+
+impl std::error::Error for GameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, in game {}", self.error, self.game)
     }
 }
 
 impl From<std::io::Error> for GameEncodeError {
     fn from(inner: std::io::Error) -> Self {
-        Self {
-            kind: GameEncodeErrorKind::IoError,
-            explanation: format!("I/O Error: {inner}"),
-        }
+        Self::new(GameEncodeErrorKind::IoError, format!("I/O Error: {inner}"))
     }
 }
 
 impl From<SanError> for GameEncodeError {
     fn from(inner: SanError) -> Self {
-        Self {
-            kind: GameEncodeErrorKind::SanError,
-            explanation: format!("Illegal or ambiguous SAN: {inner}"),
-        }
+        Self::new(
+            GameEncodeErrorKind::SanError,
+            format!("Illegal or ambiguous SAN: {inner}"),
+        )
     }
 }
 
 impl From<ParseSanError> for GameEncodeError {
     fn from(inner: ParseSanError) -> Self {
-        Self {
-            kind: GameEncodeErrorKind::SanError,
-            explanation: format!("Unable to parse SAN: {inner}"),
-        }
+        Self::new(
+            GameEncodeErrorKind::ParseSanError,
+            format!("Unable to parse SAN: {inner}"),
+        )
+    }
+}
+
+impl From<IllegalUciMoveError> for GameEncodeError {
+    fn from(inner: IllegalUciMoveError) -> Self {
+        Self::new(
+            GameEncodeErrorKind::UciError,
+            format!("Illegal UCI move: {inner}"),
+        )
+    }
+}
+
+impl From<ParseUciMoveError> for GameEncodeError {
+    fn from(inner: ParseUciMoveError) -> Self {
+        Self::new(
+            GameEncodeErrorKind::ParseUciError,
+            format!("Unable to parse UCI move: {inner}"),
+        )
     }
 }
 
 impl From<PlayError<Chess>> for GameDecodeError {
-    fn from(_: PlayError<Chess>) -> Self {
-        Self {}
+    fn from(inner: PlayError<Chess>) -> Self {
+        Self {
+            kind: GameDecodeErrorKind::IllegalMove,
+            ply: ply_index(&inner.position),
+            bit_offset: None,
+            position: Box::new(inner.position),
+        }
     }
 }
 
@@ -157,6 +364,55 @@ impl EncodedGame {
         }
     }
 
+    /// Converts a byte vector (that was the output of `to_bytes`) to an `EncodedGame`, like
+    /// `from_bytes`, but checks that the padding byte matches the length of the byte vector,
+    /// instead of silently returning a shorter or longer game.
+    ///
+    /// Use [`decode_game_strict`] to also check the padding bits.
+    ///
+    /// # Errors
+    ///
+    /// [`GameDecodeError`] with kind [`GameDecodeErrorKind::InvalidLength`] if the byte vector
+    /// is empty or its length does not match its padding byte.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_huffman::{encode_pgn, EncodedGame, GameDecodeErrorKind};
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut bytes = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. e5")?.to_bytes();
+    /// assert!(EncodedGame::from_bytes_strict(&bytes).is_ok());
+    ///
+    /// bytes.insert(0, 0);
+    /// let err = EncodedGame::from_bytes_strict(&bytes).unwrap_err();
+    /// assert_eq!(err.kind, GameDecodeErrorKind::InvalidLength);
+    /// # Ok(())
+    /// # }
+    /// # try_main().unwrap();
+    /// ```
+    pub fn from_bytes_strict(bytes: &[u8]) -> DecodeResult<Self> {
+        strict_bit_index(bytes)
+            .map(|_| Self::from_bytes(bytes))
+            .ok_or_else(invalid_length_error)
+    }
+
+    // Returns the index of the first set bit after `bit_index`, if any.
+    fn first_nonzero_padding_bit(&self) -> Option<usize> {
+        let first_word = self.bit_index / 64;
+        self.inner
+            .iter()
+            .enumerate()
+            .skip(first_word)
+            .find_map(|(i, &word)| {
+                let word = if i == first_word {
+                    word & !((1 << (self.bit_index % 64)) - 1)
+                } else {
+                    word
+                };
+                (word != 0).then(|| i * 64 + word.trailing_zeros() as usize)
+            })
+    }
+
     fn new() -> Self {
         Self {
             inner: vec![0; 256 / 64],
@@ -165,6 +421,76 @@ impl EncodedGame {
     }
 }
 
+/// Returns the number of bits in a byte vector in the format of [`EncodedGame::to_bytes`],
+/// or `None` if the length of the byte vector does not match its padding byte.
+fn strict_bit_index(bytes: &[u8]) -> Option<usize> {
+    let (&padding, content) = bytes.split_last()?;
+    let padding = padding & 0b0011_1111;
+    let bit_index = (content.len() * 8).checked_sub(usize::from(padding % 8))?;
+    #[allow(clippy::cast_possible_truncation)]
+    let expected = ((64 - bit_index % 64) % 64) as u8;
+    (content.len() == bit_index.div_ceil(8) && expected == padding).then_some(bit_index)
+}
+
+fn invalid_length_error() -> GameDecodeError {
+    GameDecodeError::new(GameDecodeErrorKind::InvalidLength, 0, 0, &Chess::default())
+}
+
+/// A view of an encoded chess game in the format of [`EncodedGame::to_bytes`], that borrows
+/// the bytes instead of copying them into an [`EncodedGame`].
+///
+/// Use [`MoveByMoveDecoder::from_view`] to decode it.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, EncodedGameView, MoveByMoveDecoder};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let bytes = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. e5")?.to_bytes();
+/// let view = EncodedGameView::from_bytes(&bytes)?;
+/// let decoder = MoveByMoveDecoder::from_view(view);
+/// assert_eq!(decoder.into_iter_moves().count(), 7);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncodedGameView<'a> {
+    bytes: &'a [u8],
+    bit_index: usize,
+}
+
+impl<'a> EncodedGameView<'a> {
+    /// Creates a view of a byte vector that was the output of [`EncodedGame::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// [`GameDecodeError`] with kind [`GameDecodeErrorKind::InvalidLength`] if the byte vector
+    /// is empty or its length does not match its padding byte.
+    pub fn from_bytes(bytes: &'a [u8]) -> DecodeResult<Self> {
+        let bit_index = strict_bit_index(bytes).ok_or_else(invalid_length_error)?;
+        Ok(Self { bytes, bit_index })
+    }
+
+    /// The bytes of the game, including the padding byte.
+    #[must_use]
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// The number of bits of the encoded moves.
+    #[must_use]
+    pub fn bit_len(&self) -> usize {
+        self.bit_index
+    }
+
+    /// Copies the game into an [`EncodedGame`].
+    #[must_use]
+    pub fn to_encoded_game(&self) -> EncodedGame {
+        EncodedGame::from_bytes(self.bytes)
+    }
+}
+
 /// Encodes a chess game into a compressed bit vector.
 ///
 /// # Arguments
@@ -244,6 +570,128 @@ pub fn encode_pgn<T: AsRef<[u8]>>(pgn: T) -> EncodeResult<EncodedGame> {
     Ok(bits)
 }
 
+/// Encodes a chess game, represented as a sequence of moves in UCI (Universal Chess Interface)
+/// notation, into a compressed bit vector.
+///
+/// Castling moves can be written both in standard notation (king moves two squares, `e1g1`)
+/// and in Chess960 notation (king captures own rook, `e1h1`).
+///
+/// # Arguments
+///
+/// * `uci` - The moves of the game in UCI notation, separated by whitespace.
+///
+/// # Errors
+///
+/// [`GameEncodeError`] if a move cannot be parsed or is illegal.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, encode_uci};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let encoded = encode_uci("e2e4 e7e5 g1f3 b8c6 f1c4 g8f6 e1g1")?;
+/// assert_eq!(encoded, encode_uci("e2e4 e7e5 g1f3 b8c6 f1c4 g8f6 e1h1")?);
+/// assert_eq!(encoded, encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. O-O")?);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn encode_uci<T: AsRef<str>>(uci: T) -> EncodeResult<EncodedGame> {
+    let mut encoder = MoveByMoveEncoder::new();
+    for token in uci.as_ref().split_whitespace() {
+        token
+            .parse::<UciMove>()
+            .map_err(GameEncodeError::from)
+            .and_then(|uci| Ok(uci.to_move(&encoder.pos)?))
+            .and_then(|m| encoder.add_move(m))
+            .map_err(|mut e| {
+                e.uci = Some(token.into());
+                e.at_position(&encoder.pos)
+            })?;
+    }
+    Ok(encoder.result)
+}
+
+/// Encodes a chess game into a compressed bit vector, like [`encode_game`], but does not
+/// discard the moves before an invalid move.
+///
+/// # Arguments
+///
+/// * `moves` - The sequence of moves that the game consists of.
+///
+/// # Examples
+///
+/// ```
+/// use shakmaty::{Move, Role, Square};
+/// use chess_huffman::{decode_game, encode_game_lenient};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let moves = vec![
+///     Move::Normal {
+///         role: Role::Pawn,
+///         from: Square::D2,
+///         to: Square::D4,
+///         capture: None,
+///         promotion: None,
+///     },
+///     Move::Normal {
+///         role: Role::Pawn,
+///         from: Square::E7,
+///         to: Square::E4,
+///         capture: None,
+///         promotion: None,
+///     }
+/// ];
+///
+/// let (encoded, error) = encode_game_lenient(&moves);
+/// assert!(error.is_some());
+/// assert_eq!(decode_game(&encoded)?.0, &moves[..1]);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[must_use]
+pub fn encode_game_lenient(moves: &[Move]) -> LenientEncodeResult {
+    let mut encoder = MoveByMoveEncoder::new();
+    for &m in moves {
+        if let Err(e) = encoder.add_move(m) {
+            return (encoder.result, Some(e));
+        }
+    }
+    (encoder.result, None)
+}
+
+/// Encodes a chess game, represented as a PGN (Portable Game Notation), into a compressed
+/// bit vector, like [`encode_pgn`], but does not discard the moves before an invalid move.
+///
+/// # Arguments
+///
+/// * `pgn` - The PGN of the game.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{decode_game, encode_pgn_lenient};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let (encoded, error) = encode_pgn_lenient("1. e4 c5 2. Nf3 e6 3. c3 d5 4. e6");
+/// assert_eq!(error.unwrap().san.as_deref(), Some("e6"));
+/// assert_eq!(decode_game(&encoded)?.0.len(), 6);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn encode_pgn_lenient<T: AsRef<[u8]>>(pgn: T) -> LenientEncodeResult {
+    let mut reader = pgn_reader::Reader::new(Cursor::new(pgn.as_ref()));
+
+    let mut encoder = pgn::Encoder::new();
+    match reader.read_game(&mut encoder) {
+        Ok(Some(Ok(bits))) => (bits, None),
+        Ok(None) => (EncodedGame::new(), None),
+        Ok(Some(Err(e))) => (encoder.into_encoded(), Some(e)),
+        Err(e) => (encoder.into_encoded(), Some(e.into())),
+    }
+}
+
 /// Encodes a chess game, stored in a PGN file, into a compressed bit vector.
 ///
 /// # Arguments
@@ -265,6 +713,102 @@ pub fn encode_pgn_file<P: AsRef<std::path::Path>>(path: P) -> EncodeResult<Encod
     Ok(bits)
 }
 
+/// A game that was read and encoded by [`encode_pgn_games`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnGame {
+    /// The encoded moves of the game.
+    pub encoded: EncodedGame,
+    /// The tag pairs (name and value) of the game, in the order they appear in the PGN.
+    pub tags: Vec<(String, String)>,
+    /// The byte offset in the PGN input where the game starts.
+    pub byte_offset: u64,
+}
+
+/// Encodes all chess games in a PGN (Portable Game Notation) stream, one by one.
+///
+/// The returned iterator yields one [`EncodeResult`] per game. A game that cannot be encoded
+/// yields an error, after which the iterator continues with the next game. Only an I/O error
+/// (or an irrecoverable PGN parser error) ends the iteration.
+///
+/// # Arguments
+///
+/// * `reader` - The source of the PGN, for example a [`std::fs::File`].
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::encode_pgn_games;
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let pgn = "[White \"Alice\"]\n1. e4 e5 *\n\n[White \"Bob\"]\n1. d4 d5 *\n";
+/// let games = encode_pgn_games(pgn.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!(games.len(), 2);
+/// assert_eq!(games[1].tags[0].1, "Bob");
+/// assert_eq!(games[1].byte_offset, 28);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn encode_pgn_games<R: std::io::Read>(
+    reader: R,
+) -> impl Iterator<Item = EncodeResult<PgnGame>> {
+    struct PgnGames<'a, R> {
+        reader: pgn_reader::Reader<pgn::CountingReader<R>>,
+        bytes_read: std::sync::Arc<std::sync::atomic::AtomicU64>,
+        encoder: pgn::Encoder<'a>,
+        done: bool,
+    }
+    impl<R: std::io::Read> PgnGames<'_, R> {
+        fn next_game(&mut self) -> EncodeResult<Option<PgnGame>> {
+            if !self.reader.has_more()? {
+                return Ok(None);
+            }
+            let byte_offset = self.bytes_read.load(std::sync::atomic::Ordering::Relaxed)
+                - self.reader.buffer().len() as u64;
+            match self.reader.read_game(&mut self.encoder)? {
+                Some(encoded) => Ok(Some(PgnGame {
+                    encoded: encoded.map_err(|mut e| {
+                        e.byte_offset = Some(byte_offset);
+                        e
+                    })?,
+                    tags: std::mem::take(&mut self.encoder.tags),
+                    byte_offset,
+                })),
+                None => Ok(None),
+            }
+        }
+    }
+    impl<R: std::io::Read> Iterator for PgnGames<'_, R> {
+        type Item = EncodeResult<PgnGame>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            match self.next_game() {
+                Ok(Some(game)) => Some(Ok(game)),
+                Ok(None) => {
+                    self.done = true;
+                    None
+                }
+                Err(e) => {
+                    // The PGN reader cannot recover from I/O errors, so stop there.
+                    // Other errors only affect the current game.
+                    self.done = e.kind == GameEncodeErrorKind::IoError;
+                    Some(Err(e))
+                }
+            }
+        }
+    }
+
+    let (reader, bytes_read) = pgn::CountingReader::new(reader);
+    PgnGames {
+        reader: pgn_reader::Reader::new(reader),
+        bytes_read,
+        encoder: pgn::Encoder::new(),
+        done: false,
+    }
+}
+
 /// Decodes a bit vector into a game, returning both a vector of all moves
 /// and all positions. The N'th position in the position vector is the
 /// position after the N'th move in the move vector.
@@ -301,6 +845,180 @@ pub fn decode_game(encoded: &EncodedGame) -> DecodeResult<(Vec<Move>, Vec<Chess>
     Ok((moves, positions))
 }
 
+/// Decodes a bit vector into a list of moves and a list of positions, like [`decode_game`],
+/// but also checks that the bit vector is well-formed: all bits after the last move
+/// must be zero.
+///
+/// Like [`decode_game`], this fails if the bit vector ends in the middle of a code, so a
+/// game that is decoded successfully always ends exactly on a code boundary.
+///
+/// # Arguments
+///
+/// * `encoded` - A bit vector of a compressed chess game.
+///
+/// # Errors
+///
+/// [`GameDecodeError`] if the game contains invalid moves, or with kind
+/// [`GameDecodeErrorKind::NonZeroPadding`] if a bit after the last move is set.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, decode_game_strict, EncodedGame, GameDecodeErrorKind};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut bytes = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. e5")?.to_bytes();
+/// assert_eq!(decode_game_strict(&EncodedGame::from_bytes_strict(&bytes)?)?.0.len(), 7);
+///
+/// // Set the last padding bit of the last byte.
+/// let len = bytes.len();
+/// bytes[len - 2] |= 0x80;
+/// let err = decode_game_strict(&EncodedGame::from_bytes_strict(&bytes)?).unwrap_err();
+/// assert_eq!(err.kind, GameDecodeErrorKind::NonZeroPadding);
+/// assert_eq!(err.ply, 7);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn decode_game_strict(encoded: &EncodedGame) -> DecodeResult<(Vec<Move>, Vec<Chess>)> {
+    let (moves, positions) = decode_game(encoded)?;
+    if let Some(bit_offset) = encoded.first_nonzero_padding_bit() {
+        return Err(GameDecodeError::new(
+            GameDecodeErrorKind::NonZeroPadding,
+            moves.len(),
+            bit_offset,
+            positions.last().unwrap_or(&Chess::default()),
+        ));
+    }
+    Ok((moves, positions))
+}
+
+/// Decodes a bit vector into a list of moves and a list of positions, like [`decode_game`],
+/// but keeps the moves and positions that were decoded before an invalid move.
+///
+/// This makes it possible to salvage most of a damaged game. The returned error
+/// tells where decoding stopped and why.
+///
+/// # Arguments
+///
+/// * `encoded` - A bit vector of a compressed chess game.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, decode_game_lossy, EncodedGame};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut bytes = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. e5")?.to_bytes();
+/// // Damage the game after the fifth move.
+/// bytes[2] = 0;
+///
+/// let (moves, positions, error) = decode_game_lossy(&EncodedGame::from_bytes(&bytes));
+/// assert_eq!(moves.len(), 5);
+/// assert_eq!(positions.len(), 5);
+/// assert_eq!(error.unwrap().ply, 5);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[must_use]
+pub fn decode_game_lossy(encoded: &EncodedGame) -> LossyDecodeResult {
+    let mut moves = vec![];
+    let mut positions = vec![];
+
+    let decoder = MoveByMoveDecoder::new(encoded);
+    for d in decoder.into_iter_moves_and_positions() {
+        match d {
+            Ok((m, pos)) => {
+                moves.push(m);
+                positions.push(pos);
+            }
+            Err(e) => return (moves, positions, Some(e)),
+        }
+    }
+
+    (moves, positions, None)
+}
+
+/// Decodes a bit vector into PGN (Portable Game Notation) movetext, with move numbers,
+/// check and checkmate suffixes, and a result token.
+///
+/// The result token is derived from the final position: `1-0`, `0-1` or `1/2-1/2` if the game
+/// ended in checkmate or a forced draw, `*` otherwise. Use [`decode_to_pgn_writer`] to include
+/// tags as well.
+///
+/// An [`EncodedGame`] only stores the moves of a game. Comments (including clock times such
+/// as `{ [%clk 0:03:00] }`), annotations and variations of the original PGN are not stored,
+/// so they cannot be written either.
+///
+/// # Arguments
+///
+/// * `encoded` - A bit vector of a compressed chess game.
+///
+/// # Errors
+///
+/// [`GameDecodeError`] if the game contains invalid moves.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, decode_to_pgn};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let encoded = encode_pgn("1. f3 e5 2. g4 Qh4#")?;
+/// assert_eq!(decode_to_pgn(&encoded)?, "1. f3 e5 2. g4 Qh4# 0-1\n");
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn decode_to_pgn(encoded: &EncodedGame) -> DecodeResult<String> {
+    let mut out = vec![];
+    pgn::write_pgn(encoded, &[], &mut out).expect("writing to a Vec cannot fail")?;
+    Ok(String::from_utf8(out).expect("PGN is written as UTF-8"))
+}
+
+/// Decodes a bit vector into a PGN (Portable Game Notation) and writes it to `writer`.
+///
+/// An [`EncodedGame`] only stores moves, so tags have to be supplied by the caller. They are
+/// written in the given order. If there is a `Result` tag, its value is used as result token;
+/// otherwise the result token is derived from the final position, as in [`decode_to_pgn`].
+/// Comments, clock times, annotations and variations are not stored, so they are not written.
+///
+/// The PGN is written while the game is decoded, move by move, so `writer` is not buffered
+/// here; pass a [`std::io::BufWriter`] for unbuffered writers such as files. If the game
+/// contains an invalid move, the moves before it have already been written.
+///
+/// # Arguments
+///
+/// * `encoded` - A bit vector of a compressed chess game.
+/// * `tags` - The tag pairs (name and value) to write before the movetext.
+/// * `writer` - Where to write the PGN to.
+///
+/// # Errors
+///
+/// An I/O error if writing fails. If the game contains invalid moves, the I/O error has kind
+/// [`std::io::ErrorKind::InvalidData`] and wraps the [`GameDecodeError`].
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, decode_to_pgn_writer};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let encoded = encode_pgn("1. e4 e5 2. Nf3")?;
+/// let tags = vec![(String::from("Event"), String::from("Casual game"))];
+/// let mut out = vec![];
+/// decode_to_pgn_writer(&encoded, &tags, &mut out)?;
+/// assert_eq!(out, b"[Event \"Casual game\"]\n\n1. e4 e5 2. Nf3 *\n");
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+pub fn decode_to_pgn_writer<W: std::io::Write>(
+    encoded: &EncodedGame,
+    tags: &[(String, String)],
+    mut writer: W,
+) -> std::io::Result<()> {
+    pgn::write_pgn(encoded, tags, &mut writer)?
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
 /// Iterator to decode a game move by move, rather than all at once.
 /// This allows for more fine-grained processing than [`decode_game`].
 ///
@@ -324,53 +1042,195 @@ pub fn decode_game(encoded: &EncodedGame) -> DecodeResult<(Vec<Move>, Vec<Chess>
 /// # Ok(())
 /// # }
 pub struct MoveByMoveDecoder<'a> {
-    bit_iter: bitm::BitIterator<'a>,
-    huff_decoder: Decoder<'a, u8>,
+    source: BitSource<'a>,
+    bit_end: usize,
     pos: Chess,
+    ply: usize,
+    bit_offset: usize,
+    failed: bool,
 }
 
 impl<'a> MoveByMoveDecoder<'a> {
     /// Construct a new [`MoveByMoveDecoder`] from an [`EncodedGame`].
     #[must_use]
     pub fn new(encoded: &'a EncodedGame) -> Self {
-        let huff_decoder = codes::get_decoder();
-        let bit_iter = encoded.inner.bit_in_range_iter(0..encoded.bit_index);
+        Self::with_source(BitSource::Words(&encoded.inner), encoded.bit_index)
+    }
+
+    /// Construct a new [`MoveByMoveDecoder`] from an [`EncodedGameView`], which decodes
+    /// straight from the borrowed bytes.
+    #[must_use]
+    pub fn from_view(view: EncodedGameView<'a>) -> Self {
+        // The padding byte is not part of the bits.
+        let content = &view.bytes[..view.bytes.len() - 1];
+        Self::with_source(BitSource::Bytes(content), view.bit_index)
+    }
+
+    fn with_source(source: BitSource<'a>, bit_end: usize) -> Self {
         Self {
-            bit_iter,
-            huff_decoder,
+            source,
+            bit_end,
             pos: Chess::default(),
+            ply: 0,
+            bit_offset: 0,
+            failed: false,
         }
     }
 }
 
 impl MoveByMoveDecoder<'_> {
     /// Returns the next move.
+    ///
+    /// After an error has been returned, the decoder does not return any more moves.
     pub fn next_move(&mut self) -> Option<DecodeResult<Move>> {
-        match self.huff_decoder.decode_next(&mut self.bit_iter) {
-            DecodingResult::Value(rank) => {
-                let m =
-                    ranking::nth_from_position(*rank as usize, &self.pos).ok_or(GameDecodeError {});
-                match m {
-                    Ok(m) => {
-                        self.pos.play_unchecked(m);
-
-                        Some(Ok(m))
-                    }
-                    Err(e) => Some(Err(e)),
-                }
-            }
-            DecodingResult::Invalid => {
-                Some(Err(GameDecodeError {}))
-                // this shouldn't happen though: according to minimum_redundancy's docs, Invalid can only be returned if the bits per fragment > 1
+        let m = self.decode_next_move()?;
+        Some(m.inspect(|&m| self.play(m)))
+    }
+
+    /// Returns the next move in SAN (Standard Algebraic Notation), including a check or
+    /// checkmate suffix.
+    pub fn next_san(&mut self) -> Option<DecodeResult<SanPlus>> {
+        let m = self.decode_next_move()?;
+        Some(m.map(|m| {
+            let san = San::from_move(&self.pos, m);
+            self.play(m);
+            SanPlus {
+                san,
+                suffix: Suffix::from_position(&self.pos),
             }
-            DecodingResult::Incomplete => {
-                if self.huff_decoder.consumed_fragments() == 0 {
-                    None
-                } else {
-                    Some(Err(GameDecodeError {}))
-                }
+        }))
+    }
+
+    /// Returns the next move, together with its rank and the length of its code.
+    pub(crate) fn next_move_with_code(&mut self) -> Option<DecodeResult<(Move, u8, u32)>> {
+        let decoded = self.decode_next()?;
+        Some(decoded.inspect(|&(m, _, _)| self.play(m)))
+    }
+
+    /// Decodes the next move, without playing it.
+    fn decode_next_move(&mut self) -> Option<DecodeResult<Move>> {
+        Some(self.decode_next()?.map(|(m, _, _)| m))
+    }
+
+    /// Decodes the next move, its rank and the length of its code, without playing the move.
+    fn decode_next(&mut self) -> Option<DecodeResult<(Move, u8, u32)>> {
+        if self.failed {
+            return None;
+        }
+        let result = codes::BOOK_FROM_LICHESS_WEIGHTS
+            .decode(self.source, self.bit_offset, self.bit_end)?
+            .and_then(|(rank, len)| {
+                let m = ranking::nth_from_position(usize::from(rank), &self.pos)
+                    .ok_or(GameDecodeErrorKind::RankOutOfRange)?;
+                self.bit_offset += len as usize;
+                Ok((m, rank, len))
+            });
+
+        Some(result.map_err(|kind| {
+            self.failed = true;
+            GameDecodeError::new(kind, self.ply, self.bit_offset, &self.pos)
+        }))
+    }
+
+    fn play(&mut self, m: Move) {
+        self.pos.play_unchecked(m);
+        self.ply += 1;
+    }
+
+    /// Returns the current position, that is, the position after the last decoded move.
+    #[must_use]
+    pub fn position(&self) -> &Chess {
+        &self.pos
+    }
+
+    /// Returns the number of moves (plies) that have been decoded so far.
+    #[must_use]
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// Skips the next `n` moves, without returning them.
+    ///
+    /// Returns the number of skipped moves, which is less than `n` if the game ends earlier.
+    ///
+    /// This is not faster than calling [`MoveByMoveDecoder::next_move`] `n` times: every
+    /// skipped move still has to be decoded and played, because the meaning of a code
+    /// depends on the position. To reach a ply without decoding all moves before it, use
+    /// [`MoveByMoveDecoder::seek_to_ply_with_checkpoints`].
+    ///
+    /// # Errors
+    ///
+    /// [`GameDecodeError`] if the game contains invalid moves.
+    pub fn skip(&mut self, n: usize) -> DecodeResult<usize> {
+        for skipped in 0..n {
+            match self.decode_next_move() {
+                Some(m) => self.play(m?),
+                None => return Ok(skipped),
             }
         }
+        Ok(n)
+    }
+
+    /// Moves the decoder to the position after `ply` moves, so the next decoded move is the
+    /// move with index `ply`. Seeking backwards restarts decoding from the start of the game.
+    ///
+    /// Returns `false` if the game has fewer than `ply` moves; the decoder is then
+    /// at the end of the game.
+    ///
+    /// # Errors
+    ///
+    /// [`GameDecodeError`] if the game contains invalid moves.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_huffman::{encode_pgn, MoveByMoveDecoder};
+    /// use shakmaty::{Position, Role, Square};
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let encoded = encode_pgn("1. e4 c5 2. Nf3 e6 3. c3 d5 4. exd5")?;
+    /// let mut decoder = MoveByMoveDecoder::new(&encoded);
+    /// assert!(decoder.seek_to_ply(6)?);
+    /// assert_eq!(decoder.position().board().role_at(Square::D5), Some(Role::Pawn));
+    /// assert_eq!(decoder.next_move().unwrap()?.to(), Square::D5);
+    /// assert!(decoder.seek_to_ply(1)?);
+    /// assert_eq!(decoder.next_move().unwrap()?.to(), Square::C5);
+    /// assert!(!decoder.seek_to_ply(10)?);
+    /// assert_eq!(decoder.ply(), 7);
+    /// # Ok(())
+    /// # }
+    /// # try_main().unwrap();
+    /// ```
+    pub fn seek_to_ply(&mut self, ply: usize) -> DecodeResult<bool> {
+        if ply < self.ply {
+            *self = MoveByMoveDecoder::with_source(self.source, self.bit_end);
+        }
+        let n = ply - self.ply;
+        Ok(self.skip(n)? == n)
+    }
+
+    /// Like [`MoveByMoveDecoder::seek_to_ply`], but starts decoding from the nearest
+    /// checkpoint at or before `ply` if that is closer than the current ply.
+    ///
+    /// `checkpoints` must have been built from the same [`EncodedGame`] as this decoder.
+    ///
+    /// # Errors
+    ///
+    /// [`GameDecodeError`] if the game contains invalid moves.
+    pub fn seek_to_ply_with_checkpoints(
+        &mut self,
+        ply: usize,
+        checkpoints: &Checkpoints,
+    ) -> DecodeResult<bool> {
+        if let Some((checkpoint_ply, bit_offset, pos)) = checkpoints.at_or_before(ply)
+            && (checkpoint_ply > self.ply || ply < self.ply)
+        {
+            self.pos = pos;
+            self.ply = checkpoint_ply;
+            self.bit_offset = bit_offset;
+            self.failed = false;
+        }
+        self.seek_to_ply(ply)
     }
 
     /// Returns the resulting position when the next move is played.
@@ -385,6 +1245,15 @@ impl MoveByMoveDecoder<'_> {
         }
     }
 
+    /// Returns the Zobrist hash of the resulting position when the next move is played.
+    ///
+    /// En passant squares are only included in the hash if an en passant capture is legal
+    /// ([`EnPassantMode::Legal`]), so equal positions always have equal hashes.
+    pub fn next_zobrist(&mut self) -> Option<DecodeResult<Zobrist64>> {
+        self.next_position()
+            .map(|r| r.map(|pos| pos.zobrist_hash(EnPassantMode::Legal)))
+    }
+
     /// Returns the next move and the resulting position when the move is played.
     pub fn next_move_and_position(&mut self) -> Option<DecodeResult<(Move, &Chess)>> {
         if let Some(move_result) = self.next_move() {
@@ -413,6 +1282,69 @@ impl MoveByMoveDecoder<'_> {
         MoveIter { decoder: self }
     }
 
+    /// Turns the decoder into an iterator over the moves in the chess game, in UCI
+    /// (Universal Chess Interface) notation.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - How castling moves are written: [`CastlingMode::Standard`] for king moves
+    ///   of two squares (`e1g1`), [`CastlingMode::Chess960`] for king captures own rook (`e1h1`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_huffman::{encode_uci, MoveByMoveDecoder};
+    /// use shakmaty::CastlingMode;
+    ///
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let encoded = encode_uci("e2e4 d7d5 e4d5 g8f6 f1b5 c8d7 b5e2 d7g4 g1f3 b8a6 e1g1")?;
+    /// let uci = MoveByMoveDecoder::new(&encoded)
+    ///     .into_iter_uci(CastlingMode::Chess960)
+    ///     .map(|r| r.map(|m| m.to_string()))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(uci.last().unwrap(), "e1h1");
+    /// # Ok(())
+    /// # }
+    /// # try_main().unwrap();
+    /// ```
+    pub fn into_iter_uci(self, mode: CastlingMode) -> impl Iterator<Item = DecodeResult<UciMove>> {
+        self.into_iter_moves()
+            .map(move |r| r.map(|m| UciMove::from_move(m, mode)))
+    }
+
+    /// Turns the decoder into an iterator over the moves in the chess game, in SAN
+    /// (Standard Algebraic Notation) with check and checkmate suffixes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_huffman::{encode_pgn, MoveByMoveDecoder};
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let encoded = encode_pgn("1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7")?;
+    /// let san = MoveByMoveDecoder::new(&encoded)
+    ///     .into_iter_san()
+    ///     .map(|r| r.map(|san| san.to_string()))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(san, ["e4", "e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"]);
+    /// # Ok(())
+    /// # }
+    /// # try_main().unwrap();
+    /// ```
+    pub fn into_iter_san(self) -> impl Iterator<Item = DecodeResult<SanPlus>> {
+        struct SanIter<'a> {
+            decoder: MoveByMoveDecoder<'a>,
+        }
+        impl Iterator for SanIter<'_> {
+            type Item = DecodeResult<SanPlus>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.decoder.next_san()
+            }
+        }
+
+        SanIter { decoder: self }
+    }
+
     /// Turns the decoder into an iterator over the positions in the chess game.
     /// The first yielded position is the position after the first move.
     pub fn into_iter_positions(self) -> impl Iterator<Item = DecodeResult<Chess>> {
@@ -430,6 +1362,42 @@ impl MoveByMoveDecoder<'_> {
         PosIter { decoder: self }
     }
 
+    /// Turns the decoder into an iterator over the Zobrist hashes of the positions in the
+    /// chess game. The first yielded hash is the hash of the position after the first move.
+    ///
+    /// This is cheaper than hashing the positions of [`MoveByMoveDecoder::into_iter_positions`],
+    /// because the positions do not have to be cloned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_huffman::{encode_pgn, MoveByMoveDecoder};
+    /// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let encoded = encode_pgn("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3")?;
+    /// let hashes = MoveByMoveDecoder::new(&encoded)
+    ///     .into_iter_zobrist()
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(hashes[0], hashes[4]);
+    /// assert_ne!(hashes[0], hashes[1]);
+    /// # Ok(())
+    /// # }
+    /// # try_main().unwrap();
+    /// ```
+    pub fn into_iter_zobrist(self) -> impl Iterator<Item = DecodeResult<Zobrist64>> {
+        struct ZobristIter<'a> {
+            decoder: MoveByMoveDecoder<'a>,
+        }
+        impl Iterator for ZobristIter<'_> {
+            type Item = DecodeResult<Zobrist64>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.decoder.next_zobrist()
+            }
+        }
+
+        ZobristIter { decoder: self }
+    }
+
     /// Turns the decoder into an iterator over the moves and positions in the chess game.
     /// The yielded position is the position after the move.
     pub fn into_iter_moves_and_positions(
@@ -516,22 +1484,24 @@ impl MoveByMoveEncoder<'_> {
         match ranking::move_rank(&self.pos, m) {
             Some(rank) => {
                 if rank > 255 {
-                    return Err(GameEncodeError {
-                        kind: GameEncodeErrorKind::HuffmanEncodeError,
-                        explanation: String::from(
+                    return Err(GameEncodeError::new(
+                        GameEncodeErrorKind::HuffmanEncodeError,
+                        String::from(
                             "Too many possible valid moves - is this a valid chess position?",
                         ),
-                    });
+                    )
+                    .at_position(&self.pos));
                 }
                 #[allow(clippy::cast_possible_truncation)]
                 self.book.encode(&mut self.result, rank as u8);
                 self.pos.play_unchecked(m);
             }
             None => {
-                return Err(GameEncodeError {
-                    kind: GameEncodeErrorKind::IllegalMove,
-                    explanation: format!("Illegal move {m}"),
-                });
+                return Err(GameEncodeError::new(
+                    GameEncodeErrorKind::IllegalMove,
+                    format!("Illegal move {m}"),
+                )
+                .at_position(&self.pos));
             }
         }
 
@@ -545,6 +1515,11 @@ impl MoveByMoveEncoder<'_> {
     }
 }
 
+/// The index of the ply (half-move, starting from 0) that is to be played in `pos`.
+fn ply_index(pos: &Chess) -> usize {
+    (pos.fullmoves().get() as usize - 1) * 2 + usize::from(pos.turn().is_black())
+}
+
 impl Default for MoveByMoveEncoder<'_> {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,284 @@
+use crate::EncodedGameView;
+use crate::archive::{
+    DELETION_LEN, HEADER_LEN, INDEX_ENTRY_LEN, IndexEntry, Segment, deleted_error, invalid_data,
+    parse_header, read_segments,
+};
+use memmap2::Mmap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Reads the games of an archive that was written by [`crate::GameArchiveWriter`], by
+/// memory-mapping the archive file instead of reading it into memory.
+///
+/// Games are returned as [`EncodedGameView`]s that borrow the mapped bytes, so they can
+/// be decoded with [`crate::MoveByMoveDecoder::from_view`] without copying them.
+///
+/// Nothing is validated when the archive is opened. The header, the trailers and the deleted
+/// game numbers are validated on the first access, and each index entry when the game it
+/// points to is read. All methods take `&self`, so one archive can be shared by many threads.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use chess_huffman::{MmapGameArchive, MoveByMoveDecoder};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// // SAFETY: no archive file at this path is truncated while it is mapped.
+/// let archive = unsafe { MmapGameArchive::open("games.chga")? };
+/// let decoder = MoveByMoveDecoder::from_view(archive.game(1000)?);
+/// for m in decoder.into_iter_moves() {
+///     println!("{}", m?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct MmapGameArchive {
+    map: Mmap,
+    layout: OnceLock<Result<Layout, String>>,
+}
+
+#[derive(Debug)]
+struct Layout {
+    codebook_id: u16,
+    count: u64,
+    segments: Vec<Segment>,
+    // The game numbers of the games deleted by later segments.
+    deleted: HashSet<u64>,
+}
+
+impl MmapGameArchive {
+    /// Memory-maps the archive file at `path`.
+    ///
+    /// Appending to the file with [`crate::GameArchiveFile`] leaves the mapped bytes alone,
+    /// and a mapped archive also stays valid when it is replaced by
+    /// [`crate::GameArchiveFile::compact`]. Changes are only seen by archives that are mapped
+    /// after them.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified in place while the archive (or a view of
+    /// one of its games) is alive; accessing the mapping could then crash the process or
+    /// return changed bytes. In particular, a [`crate::GameArchiveFile`] that is opened
+    /// after a commit to the file was interrupted rolls the commit back by truncating the
+    /// file, so the file must not be opened with [`crate::GameArchiveFile::open`] while it
+    /// is mapped, unless no commit to it can have been interrupted.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error when opening or mapping the file.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only, and the caller guarantees that the file is not
+        // truncated or modified in place while it is mapped.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            map,
+            layout: OnceLock::new(),
+        })
+    }
+
+    fn layout(&self) -> io::Result<&Layout> {
+        self.layout
+            .get_or_init(|| self.read_layout().map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| invalid_data(e.clone()))
+    }
+
+    fn read_layout(&self) -> io::Result<Layout> {
+        // `read_segments` checks that the archive is long enough for a header, and only reads
+        // trailers that lie within the archive.
+        let segments = read_segments(self.map.len() as u64, |offset, buf| {
+            let start = offset as usize;
+            buf.copy_from_slice(&self.map[start..start + buf.len()]);
+            Ok(())
+        })?;
+        let codebook_id = parse_header(&self.map[..HEADER_LEN as usize])?;
+        let mut deleted = HashSet::new();
+        for segment in &segments {
+            let start =
+                (segment.index_offset + (segment.count - segment.first) * INDEX_ENTRY_LEN) as usize;
+            let bytes = &self.map[start..start + (segment.deletions * DELETION_LEN) as usize];
+            for deletion in bytes.chunks_exact(DELETION_LEN as usize) {
+                deleted.insert(segment.deleted_game(deletion)?);
+            }
+        }
+        Ok(Layout {
+            codebook_id,
+            count: segments.last().map_or(0, |segment| segment.count),
+            segments,
+            deleted,
+        })
+    }
+
+    /// The number of games in the archive, including deleted games.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the header or trailer is invalid.
+    pub fn len(&self) -> io::Result<usize> {
+        usize::try_from(self.layout()?.count).map_err(|_| invalid_data("archive is too large"))
+    }
+
+    /// Whether the archive contains no games, not even deleted ones.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the header or trailer is invalid.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The id of the codebook the games in the archive were encoded with.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the header or trailer is invalid.
+    pub fn codebook_id(&self) -> io::Result<u16> {
+        Ok(self.layout()?.codebook_id)
+    }
+
+    /// Returns a view of the game with game number `n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the archive.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] if the game was deleted, or
+    /// [`io::ErrorKind::InvalidData`] if the header, the trailer, the index entry of
+    /// the game or the game itself is invalid.
+    pub fn game(&self, n: usize) -> io::Result<EncodedGameView<'_>> {
+        let layout = self.layout()?;
+        let n = n as u64;
+        assert!(n < layout.count, "game number out of range");
+
+        // The trailer checks guarantee that the index entries of all segments lie within the
+        // map.
+        let segment = layout.segments[layout.segments.partition_point(|s| s.count <= n)];
+        let entry_start = (segment.index_offset + (n - segment.first) * INDEX_ENTRY_LEN) as usize;
+        let entry = IndexEntry::parse(
+            &self.map[entry_start..entry_start + INDEX_ENTRY_LEN as usize],
+            segment.index_offset,
+        )?;
+        if entry.is_deleted() || layout.deleted.contains(&n) {
+            return Err(deleted_error());
+        }
+        let start = entry.offset as usize;
+        EncodedGameView::from_bytes(&self.map[start..start + entry.len as usize])
+            .map_err(|e| invalid_data(e.to_string()))
+    }
+
+    /// Returns an iterator over views of all games in the archive that were not deleted,
+    /// in order.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the header or trailer is invalid.
+    pub fn games(&self) -> io::Result<impl Iterator<Item = io::Result<EncodedGameView<'_>>>> {
+        Ok((0..self.len()?).map(|n| self.game(n)).filter(|game| {
+            game.as_ref()
+                .map_or_else(|e| e.kind() != io::ErrorKind::NotFound, |_| true)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::TRAILER_LEN;
+    use crate::test_util::{GAMES as PGNS, TempDir};
+    use crate::{GameArchiveFile, GameArchiveWriter, MoveByMoveDecoder, decode_game, encode_pgn};
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Writes `contents` to an archive file in a new temporary directory.
+    fn archive_file(name: &str, contents: &[u8]) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        let path = dir.file("games.chga");
+        fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn concurrent_zero_copy_reads() {
+        let mut writer = GameArchiveWriter::new(vec![]).unwrap();
+        for pgn in PGNS {
+            writer.add(&encode_pgn(pgn).unwrap()).unwrap();
+        }
+        let (_dir, path) = archive_file("concurrent", &writer.finish().unwrap());
+
+        // SAFETY: the file is only changed by this test.
+        let archive = unsafe { MmapGameArchive::open(&path).unwrap() };
+        assert_eq!(archive.len().unwrap(), PGNS.len());
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (view, pgn) in archive.games().unwrap().zip(PGNS) {
+                        let moves: Vec<_> = MoveByMoveDecoder::from_view(view.unwrap())
+                            .into_iter_moves()
+                            .map(Result::unwrap)
+                            .collect();
+                        assert_eq!(moves, decode_game(&encode_pgn(pgn).unwrap()).unwrap().0);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn deleted_games() {
+        let dir = TempDir::new("deleted");
+        let path = dir.file("games.chga");
+        let mut archive = GameArchiveFile::create(&path).unwrap();
+        for pgn in PGNS {
+            archive.append(&encode_pgn(pgn).unwrap()).unwrap();
+        }
+        // Game 3 is deleted before it is committed, game 1 by a later commit.
+        archive.delete(3);
+        archive.commit().unwrap();
+        archive.delete(1);
+        archive.commit().unwrap();
+
+        drop(archive);
+
+        // SAFETY: the file is only changed by this test.
+        let archive = unsafe { MmapGameArchive::open(&path).unwrap() };
+        assert_eq!(archive.len().unwrap(), PGNS.len());
+        assert_eq!(archive.game(1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(archive.game(3).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(archive.games().unwrap().count(), PGNS.len() - 2);
+    }
+
+    #[test]
+    fn lazy_validation() {
+        let mut writer = GameArchiveWriter::new(vec![]).unwrap();
+        for pgn in PGNS {
+            writer.add(&encode_pgn(pgn).unwrap()).unwrap();
+        }
+        let mut bytes = writer.finish().unwrap();
+        // Corrupt the index entry of the second game (of four).
+        let entry_len_at = bytes.len() - TRAILER_LEN as usize - 3 * INDEX_ENTRY_LEN as usize + 8;
+        bytes[entry_len_at..entry_len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let (_dir, path) = archive_file("lazy", &bytes);
+
+        // SAFETY: the file is only changed by this test.
+        let archive = unsafe { MmapGameArchive::open(&path).unwrap() };
+        assert!(archive.game(0).is_ok());
+        assert_eq!(
+            archive.game(1).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(archive.game(2).is_ok());
+
+        let (_dir, path) = archive_file("invalid", b"not an archive, but long enough for one");
+        // SAFETY: the file is only changed by this test.
+        let archive = unsafe { MmapGameArchive::open(&path).unwrap() };
+        assert_eq!(
+            archive.len().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,16 +1,26 @@
-use crate::{EncodedGame, GameEncodeError, MoveByMoveEncoder};
-use pgn_reader::{SanPlus, Skip, Visitor};
+use crate::{DecodeResult, EncodedGame, GameEncodeError, MoveByMoveDecoder, MoveByMoveEncoder};
+use pgn_reader::{RawTag, SanPlus, Skip, Visitor};
 use shakmaty::san::San;
+use shakmaty::{Color, Position};
+use std::io::{self, Read};
 use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The PGN export format asks for lines of at most 80 characters.
+const MAX_LINE_LENGTH: usize = 80;
 
 pub struct Encoder<'a> {
     mbm: MoveByMoveEncoder<'a>,
+    /// The tags of the game that is currently (or was last) being encoded.
+    pub tags: Vec<(String, String)>,
 }
 
 impl Encoder<'_> {
     pub fn new() -> Self {
         Self {
             mbm: MoveByMoveEncoder::new(),
+            tags: vec![],
         }
     }
 
@@ -24,6 +34,18 @@ impl Encoder<'_> {
         self.mbm.add_move(m)?;
         Ok(())
     }
+
+    /// The moves of the current game that were encoded so far.
+    pub fn into_encoded(self) -> EncodedGame {
+        self.mbm.result
+    }
+
+    fn san_with_location(&mut self, san_plus: SanPlus) -> Result<(), GameEncodeError> {
+        self.san_may_error(san_plus).map_err(|mut e| {
+            e.san = Some(san_plus.to_string().into());
+            e.at_position(&self.mbm.pos)
+        })
+    }
 }
 
 impl Default for Encoder<'_> {
@@ -33,15 +55,30 @@ impl Default for Encoder<'_> {
 }
 
 impl Visitor for Encoder<'_> {
-    type Tags = ();
+    type Tags = Vec<(String, String)>;
     type Movetext = Option<GameEncodeError>;
     type Output = std::result::Result<EncodedGame, GameEncodeError>;
 
     fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
+        self.tags.clear();
+        ControlFlow::Continue(vec![])
+    }
+
+    fn tag(
+        &mut self,
+        tags: &mut Self::Tags,
+        name: &[u8],
+        value: RawTag<'_>,
+    ) -> ControlFlow<Self::Output> {
+        tags.push((
+            String::from_utf8_lossy(name).into_owned(),
+            value.decode_utf8_lossy().into_owned(),
+        ));
         ControlFlow::Continue(())
     }
 
-    fn begin_movetext(&mut self, _tags: Self::Tags) -> ControlFlow<Self::Output, Self::Movetext> {
+    fn begin_movetext(&mut self, tags: Self::Tags) -> ControlFlow<Self::Output, Self::Movetext> {
+        self.tags = tags;
         self.mbm.clear();
         ControlFlow::Continue(None)
     }
@@ -52,7 +89,7 @@ impl Visitor for Encoder<'_> {
         _movetext: &mut Self::Movetext,
         san_plus: SanPlus,
     ) -> ControlFlow<Self::Output> {
-        match self.san_may_error(san_plus) {
+        match self.san_with_location(san_plus) {
             Ok(()) => ControlFlow::Continue(()),
             Err(e) => ControlFlow::Break(Err(e)),
         }
@@ -70,6 +107,102 @@ impl Visitor for Encoder<'_> {
     }
 }
 
+/// A reader that keeps track of how many bytes have been read from it,
+/// so the byte offset of each game in a PGN stream can be reported.
+///
+/// The counter is shared, because `pgn_reader::Reader` does not give access
+/// to the reader it wraps.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> (Self, Arc<AtomicU64>) {
+        let count = Arc::new(AtomicU64::new(0));
+        (
+            Self {
+                inner,
+                count: Arc::clone(&count),
+            },
+            count,
+        )
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Decodes `encoded` into PGN text: a tag section built from `tags` (if any),
+/// followed by the movetext and a result token. Each move is written to `writer` as soon
+/// as it is decoded. Returns the I/O error of `writer` or the decode error, if any.
+pub fn write_pgn<W: io::Write>(
+    encoded: &EncodedGame,
+    tags: &[(String, String)],
+    writer: &mut W,
+) -> io::Result<DecodeResult<()>> {
+    for (name, value) in tags {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(writer, "[{name} \"{value}\"]")?;
+    }
+    if !tags.is_empty() {
+        writeln!(writer)?;
+    }
+
+    let mut movetext = Movetext::default();
+    let mut decoder = MoveByMoveDecoder::new(encoded);
+    loop {
+        // Move numbers are kept on the same line as the move they belong to.
+        let pos = decoder.position();
+        let number = match pos.turn() {
+            Color::White => format!("{}. ", pos.fullmoves()),
+            Color::Black if decoder.ply() == 0 => format!("{}... ", pos.fullmoves()),
+            Color::Black => String::new(),
+        };
+        match decoder.next_san() {
+            Some(Ok(san)) => movetext.push(writer, &format!("{number}{san}"))?,
+            Some(Err(e)) => return Ok(Err(e)),
+            None => break,
+        }
+    }
+
+    let result = tags.iter().find(|(name, _)| name == "Result").map_or_else(
+        || decoder.position().outcome().to_string(),
+        |(_, value)| value.clone(),
+    );
+    movetext.push(writer, &result)?;
+    writeln!(writer)?;
+    Ok(Ok(()))
+}
+
+/// Movetext that is wrapped to lines of at most [`MAX_LINE_LENGTH`] characters.
+#[derive(Default)]
+struct Movetext {
+    line_length: usize,
+}
+
+impl Movetext {
+    fn push<W: io::Write>(&mut self, writer: &mut W, token: &str) -> io::Result<()> {
+        if self.line_length > 0 {
+            if self.line_length + 1 + token.len() > MAX_LINE_LENGTH {
+                writer.write_all(b"\n")?;
+                self.line_length = 0;
+            } else {
+                writer.write_all(b" ")?;
+                self.line_length += 1;
+            }
+        }
+        writer.write_all(token.as_bytes())?;
+        self.line_length += token.len();
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
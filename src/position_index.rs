@@ -0,0 +1,536 @@
+use crate::archive::invalid_data;
+use crate::{DecodeResult, EncodedGame, MoveByMoveDecoder, SearchHit};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, EnPassantMode};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const MAGIC: [u8; 4] = *b"CHPI";
+const VERSION: u16 = 1;
+
+// Magic, version and two reserved bytes.
+const HEADER_LEN: usize = 8;
+// Hash and offset of its posting list.
+const TABLE_ENTRY_LEN: usize = 16;
+// Table offset, number of hashes and magic.
+const TRAILER_LEN: usize = 20;
+
+/// Collects the positions of games, to write a [`PositionIndex`].
+///
+/// Positions can be added one by one, for example while games are encoded with a
+/// [`crate::MoveByMoveEncoder`], or a whole encoded game at once.
+///
+/// The builder keeps the positions it collects in memory. To index more games than fit in
+/// memory, build the index in steps: write an index of the first batch of games with
+/// [`PositionIndexBuilder::write_to`], and then extend it with a builder for each further
+/// batch with [`PositionIndexBuilder::write_merged_to`].
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, PositionIndex, PositionIndexBuilder, SearchHit};
+/// use shakmaty::{fen::Fen, CastlingMode, Chess};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut builder = PositionIndexBuilder::new();
+/// builder.add_game(0, &encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5")?)?;
+/// builder.add_game(1, &encode_pgn("1. Nf3 Nc6 2. e4 e5 3. Bc4")?)?;
+/// let mut bytes = vec![];
+/// builder.write_to(&mut bytes)?;
+///
+/// let index = PositionIndex::new(bytes)?;
+/// let target: Chess = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+///     .parse::<Fen>()?
+///     .into_position(CastlingMode::Standard)?;
+/// assert_eq!(
+///     index.lookup(&target)?,
+///     [SearchHit { game: 0, ply: 4 }, SearchHit { game: 1, ply: 4 }]
+/// );
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PositionIndexBuilder {
+    postings: HashMap<u64, Vec<SearchHit>>,
+}
+
+impl PositionIndexBuilder {
+    /// Creates an empty builder.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds that `pos` occurs in game `game` after `ply` moves.
+    pub fn add_position(&mut self, game: usize, ply: usize, pos: &Chess) {
+        let hash: Zobrist64 = pos.zobrist_hash(EnPassantMode::Legal);
+        self.postings
+            .entry(hash.0)
+            .or_default()
+            .push(SearchHit { game, ply });
+    }
+
+    /// Adds all positions of an encoded game, including the initial position, as game `game`.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the game contains invalid moves. The positions before
+    /// the invalid move are still added.
+    pub fn add_game(&mut self, game: usize, encoded: &EncodedGame) -> DecodeResult<()> {
+        let mut decoder = MoveByMoveDecoder::new(encoded);
+        self.add_position(game, 0, decoder.position());
+        while let Some(result) = decoder.next_move() {
+            result?;
+            self.add_position(game, decoder.ply(), decoder.position());
+        }
+        Ok(())
+    }
+
+    /// The number of distinct positions added so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Whether no positions have been added yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+
+    /// Writes the index to `writer`.
+    ///
+    /// The index consists of a header (the magic bytes `CHPI` and the format version),
+    /// the posting lists, a table of the position hashes (sorted, each with the offset of its
+    /// posting list) and a trailer (the offset of the table, the number of hashes and the
+    /// magic bytes again). A posting list is the number of postings followed by the postings,
+    /// sorted by game and ply, each as the difference with the previous game id and
+    /// the ply (or the difference with the previous ply, in the same game), as varints.
+    ///
+    /// The posting lists are written one by one, and only the table is collected in memory
+    /// before it is written.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error of `writer`.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut out = IndexWriter::new(writer)?;
+        for hash in self.sorted_hashes() {
+            out.push(hash, self.postings[&hash].clone())?;
+        }
+        out.finish()
+    }
+
+    /// Writes an index with the positions of both `index` and this builder to `writer`,
+    /// in the format of [`PositionIndexBuilder::write_to`].
+    ///
+    /// `index` is read in place, one posting list at a time, so this extends an index
+    /// without loading it into memory. `writer` must not write to the bytes of `index`:
+    /// write to a new file, and replace the old index with it when it is complete.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if `index` is corrupted, or any I/O
+    /// error of `writer`.
+    pub fn write_merged_to<B, W>(&self, index: &PositionIndex<B>, writer: W) -> io::Result<()>
+    where
+        B: AsRef<[u8]>,
+        W: Write,
+    {
+        let mut out = IndexWriter::new(writer)?;
+        let mut added = self.sorted_hashes().into_iter().peekable();
+        let mut previous = None;
+        for i in 0..index.count {
+            let hash = index.hash_at(i);
+            if previous.is_some_and(|previous| previous >= hash) {
+                return Err(invalid_data("position index table is not sorted"));
+            }
+            previous = Some(hash);
+
+            while let Some(added_hash) = added.next_if(|&added_hash| added_hash < hash) {
+                out.push(added_hash, self.postings[&added_hash].clone())?;
+            }
+            let mut postings = index.postings_at(i)?;
+            if added.next_if_eq(&hash).is_some() {
+                postings.extend_from_slice(&self.postings[&hash]);
+            }
+            out.push(hash, postings)?;
+        }
+        for hash in added {
+            out.push(hash, self.postings[&hash].clone())?;
+        }
+        out.finish()
+    }
+
+    fn sorted_hashes(&self) -> Vec<u64> {
+        let mut hashes: Vec<u64> = self.postings.keys().copied().collect();
+        hashes.sort_unstable();
+        hashes
+    }
+}
+
+// Writes an index, one posting list at a time, in increasing order of the hashes.
+struct IndexWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    table: Vec<u8>,
+    count: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> IndexWriter<W> {
+    fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        Ok(Self {
+            writer,
+            offset: HEADER_LEN as u64,
+            table: vec![],
+            count: 0,
+            buf: vec![],
+        })
+    }
+
+    fn push(&mut self, hash: u64, mut postings: Vec<SearchHit>) -> io::Result<()> {
+        self.table.write_u64::<LittleEndian>(hash)?;
+        self.table.write_u64::<LittleEndian>(self.offset)?;
+        self.count += 1;
+
+        postings.sort_unstable();
+        postings.dedup();
+        self.buf.clear();
+        write_varint(&mut self.buf, postings.len() as u64);
+        let mut previous = SearchHit { game: 0, ply: 0 };
+        for hit in postings {
+            write_varint(&mut self.buf, (hit.game - previous.game) as u64);
+            if hit.game == previous.game {
+                write_varint(&mut self.buf, (hit.ply - previous.ply) as u64);
+            } else {
+                write_varint(&mut self.buf, hit.ply as u64);
+            }
+            previous = hit;
+        }
+        self.writer.write_all(&self.buf)?;
+        self.offset += self.buf.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(&self.table)?;
+        self.writer.write_u64::<LittleEndian>(self.offset)?;
+        self.writer.write_u64::<LittleEndian>(self.count)?;
+        self.writer.write_all(&MAGIC)?;
+        self.writer.flush()
+    }
+}
+
+/// An index from positions to the games and plies at which they occur, that was written by
+/// [`PositionIndexBuilder`].
+///
+/// The index is queried in place: `bytes` can be a `Vec<u8>` or a memory-mapped file.
+/// A query is a binary search in the table of position hashes, followed by decoding one
+/// posting list.
+///
+/// Positions are compared by their Zobrist hashes, so in very rare cases a lookup can return
+/// a game that has a different position with the same hash.
+#[derive(Debug, Clone)]
+pub struct PositionIndex<B: AsRef<[u8]>> {
+    bytes: B,
+    table_offset: usize,
+    count: usize,
+}
+
+impl<B: AsRef<[u8]>> PositionIndex<B> {
+    /// Opens an index, by validating its header and trailer.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if `bytes` is not a valid index
+    /// (or one of an unsupported version).
+    pub fn new(bytes: B) -> io::Result<Self> {
+        let data = bytes.as_ref();
+        if data.len() < HEADER_LEN + TRAILER_LEN || data[..4] != MAGIC {
+            return Err(invalid_data("not a position index"));
+        }
+        if LittleEndian::read_u16(&data[4..6]) != VERSION {
+            return Err(invalid_data("unsupported position index version"));
+        }
+        let trailer = &data[data.len() - TRAILER_LEN..];
+        if trailer[16..] != MAGIC {
+            return Err(invalid_data("position index trailer is missing"));
+        }
+        let table_offset = usize::try_from(LittleEndian::read_u64(&trailer[..8]));
+        let count = usize::try_from(LittleEndian::read_u64(&trailer[8..16]));
+        let (Ok(table_offset), Ok(count)) = (table_offset, count) else {
+            return Err(invalid_data("position index is too large"));
+        };
+        let fits = count
+            .checked_mul(TABLE_ENTRY_LEN)
+            .and_then(|len| len.checked_add(table_offset))
+            .and_then(|end| end.checked_add(TRAILER_LEN))
+            == Some(data.len());
+        if table_offset < HEADER_LEN || !fits {
+            return Err(invalid_data(
+                "position index table does not fit in the index",
+            ));
+        }
+        Ok(Self {
+            bytes,
+            table_offset,
+            count,
+        })
+    }
+
+    /// The number of distinct positions in the index.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Whether the index contains no positions.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the games and plies at which `pos` occurs, sorted by game and ply.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the posting list is corrupted.
+    pub fn lookup(&self, pos: &Chess) -> io::Result<Vec<SearchHit>> {
+        self.lookup_hash(pos.zobrist_hash(EnPassantMode::Legal))
+    }
+
+    /// Returns the games and plies at which a position with Zobrist hash `hash` (computed
+    /// with [`EnPassantMode::Legal`]) occurs, sorted by game and ply.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the posting list is corrupted.
+    pub fn lookup_hash(&self, hash: Zobrist64) -> io::Result<Vec<SearchHit>> {
+        let (mut low, mut high) = (0, self.count);
+        let i = loop {
+            if low >= high {
+                return Ok(vec![]);
+            }
+            let mid = low + (high - low) / 2;
+            match self.hash_at(mid).cmp(&hash.0) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => break mid,
+            }
+        };
+        self.postings_at(i)
+    }
+
+    // The entry with index `i` of the table.
+    fn entry(&self, i: usize) -> &[u8] {
+        let start = self.table_offset + i * TABLE_ENTRY_LEN;
+        &self.bytes.as_ref()[start..start + TABLE_ENTRY_LEN]
+    }
+
+    // The hash of the entry with index `i` of the table.
+    fn hash_at(&self, i: usize) -> u64 {
+        LittleEndian::read_u64(self.entry(i))
+    }
+
+    // The posting list of the entry with index `i` of the table.
+    fn postings_at(&self, i: usize) -> io::Result<Vec<SearchHit>> {
+        let offset = usize::try_from(LittleEndian::read_u64(&self.entry(i)[8..]))
+            .ok()
+            .filter(|&offset| offset >= HEADER_LEN && offset < self.table_offset)
+            .ok_or_else(|| invalid_data("posting list offset is out of bounds"))?;
+        read_postings(&self.bytes.as_ref()[offset..self.table_offset])
+            .ok_or_else(|| invalid_data("posting list is corrupted"))
+    }
+}
+
+fn read_postings(mut bytes: &[u8]) -> Option<Vec<SearchHit>> {
+    let len = usize::try_from(read_varint(&mut bytes)?).ok()?;
+    let mut postings = Vec::with_capacity(len.min(bytes.len()));
+    let mut previous = SearchHit { game: 0, ply: 0 };
+    for _ in 0..len {
+        let game_delta = usize::try_from(read_varint(&mut bytes)?).ok()?;
+        let ply = usize::try_from(read_varint(&mut bytes)?).ok()?;
+        let hit = if game_delta == 0 {
+            SearchHit {
+                game: previous.game,
+                ply: previous.ply.checked_add(ply)?,
+            }
+        } else {
+            SearchHit {
+                game: previous.game.checked_add(game_delta)?,
+                ply,
+            }
+        };
+        postings.push(hit);
+        previous = hit;
+    }
+    Some(postings)
+}
+
+/// Writes `n` as a LEB128 varint: 7 bits per byte, least significant first, with the high bit
+/// set on all bytes but the last.
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        #[allow(clippy::cast_possible_truncation)]
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    out.push(n as u8);
+}
+
+/// Reads a varint that was written by [`write_varint`] from the start of `bytes`, and advances
+/// `bytes` past it.
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::games;
+    use crate::{PositionSearch, decode_game};
+    use shakmaty::Position;
+
+    #[test]
+    fn index_matches_search() {
+        let games = games();
+        let mut builder = PositionIndexBuilder::new();
+        // Game ids do not have to be consecutive, nor added in order.
+        for (game, encoded) in games.iter().enumerate().rev() {
+            builder.add_game(game * 1000, encoded).unwrap();
+        }
+        let mut bytes = vec![];
+        builder.write_to(&mut bytes).unwrap();
+        let index = PositionIndex::new(&bytes[..]).unwrap();
+        assert_eq!(index.len(), builder.len());
+
+        for encoded in &games {
+            let positions =
+                std::iter::once(Chess::default()).chain(decode_game(encoded).unwrap().1);
+            for pos in positions {
+                let expected: Vec<SearchHit> = PositionSearch::new(&pos)
+                    .search(&games)
+                    .map(|hit| {
+                        let hit = hit.unwrap();
+                        SearchHit {
+                            game: hit.game * 1000,
+                            ply: hit.ply,
+                        }
+                    })
+                    .collect();
+                assert_eq!(index.lookup(&pos).unwrap(), expected);
+            }
+        }
+
+        let never_reached: Chess = Chess::default().swap_turn().unwrap();
+        assert!(index.lookup(&never_reached).unwrap().is_empty());
+    }
+
+    #[test]
+    fn index_built_in_two_steps() {
+        let games = games();
+        let mut all = PositionIndexBuilder::new();
+        let (mut first, mut second) = (PositionIndexBuilder::new(), PositionIndexBuilder::new());
+        for (game, encoded) in games.iter().enumerate() {
+            all.add_game(game, encoded).unwrap();
+            let step = if game % 2 == 0 {
+                &mut first
+            } else {
+                &mut second
+            };
+            step.add_game(game, encoded).unwrap();
+        }
+        // Each step has positions that the other one does not have, and the initial
+        // position is in both.
+        assert!(first.len() < all.len() && second.len() < all.len());
+
+        let mut first_bytes = vec![];
+        first.write_to(&mut first_bytes).unwrap();
+        let first_index = PositionIndex::new(&first_bytes[..]).unwrap();
+        let mut merged = vec![];
+        second.write_merged_to(&first_index, &mut merged).unwrap();
+        let mut expected = vec![];
+        all.write_to(&mut expected).unwrap();
+        assert_eq!(merged, expected);
+
+        let index = PositionIndex::new(merged).unwrap();
+        assert_eq!(index.len(), all.len());
+        let hits = index.lookup(&Chess::default()).unwrap();
+        assert_eq!(
+            hits.iter().map(|hit| hit.game).collect::<Vec<_>>(),
+            [0, 1, 1, 1, 2, 3]
+        );
+
+        // Merging with an empty builder copies the index.
+        let mut copy = vec![];
+        PositionIndexBuilder::new()
+            .write_merged_to(&first_index, &mut copy)
+            .unwrap();
+        assert_eq!(copy, first_bytes);
+    }
+
+    #[test]
+    fn invalid_index() {
+        let mut builder = PositionIndexBuilder::new();
+        builder.add_game(0, &games()[0]).unwrap();
+        let mut bytes = vec![];
+        builder.write_to(&mut bytes).unwrap();
+
+        assert!(PositionIndex::new(&bytes[..]).is_ok());
+        assert!(PositionIndex::new(&bytes[1..]).is_err());
+        assert!(PositionIndex::new(&bytes[..bytes.len() - 1]).is_err());
+        assert!(PositionIndex::new(&[][..]).is_err());
+
+        let empty = {
+            let mut bytes = vec![];
+            PositionIndexBuilder::new().write_to(&mut bytes).unwrap();
+            bytes
+        };
+        let empty = PositionIndex::new(empty).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.lookup(&Chess::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        let numbers = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u64::from(u32::MAX),
+            u64::MAX,
+        ];
+        let mut out = vec![];
+        for n in numbers {
+            write_varint(&mut out, n);
+        }
+        assert_eq!(out[..5], [0, 1, 127, 0x80, 1]);
+        let mut bytes = &out[..];
+        for n in numbers {
+            assert_eq!(read_varint(&mut bytes), Some(n));
+        }
+        assert!(bytes.is_empty());
+        assert_eq!(read_varint(&mut &[0x80][..]), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{DecodeResult, EncodedGame, MoveByMoveDecoder, SearchError, SearchHit, SearchResult};
+use shakmaty::{Bitboard, ByColor, ByRole, Chess, Color, Move, Position, Role};
+use std::borrow::Borrow;
+use std::fmt;
+use std::sync::Arc;
+
+/// A condition on the positions and moves of a game, to find games by material, patterns
+/// or special moves.
+///
+/// A query is evaluated on every position of a game (including the initial position),
+/// together with the move that led to it (none for the initial position). Queries can be
+/// combined with [`Query::and`], [`Query::or`] and [`Query::not`].
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, Query, SearchHit};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let games = [
+///     encode_pgn("1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5")?,
+///     encode_pgn("1. h4 g5 2. hxg5 h6 3. gxh6 Bg7 4. hxg7 Nf6 5. gxh8=N")?,
+/// ];
+///
+/// let underpromotion = Query::underpromotion();
+/// let hits: Vec<SearchHit> = underpromotion.scan(&games).collect::<Result<_, _>>()?;
+/// assert_eq!(hits, [SearchHit { game: 1, ply: 9 }]);
+///
+/// let queen_out = Query::custom(|_, m| m.is_some_and(|m| m.role() == shakmaty::Role::Queen));
+/// let early = Query::after_move(3).not();
+/// let hits: Vec<SearchHit> = queen_out.and(early).scan(&games).collect::<Result<_, _>>()?;
+/// assert_eq!(hits, [SearchHit { game: 0, ply: 4 }]);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Query(Node);
+
+type CustomPredicate = dyn Fn(&Chess, Option<Move>) -> bool + Send + Sync;
+
+#[derive(Clone)]
+enum Node {
+    Material(ByColor<ByRole<u8>>),
+    OppositeColoredBishops,
+    Underpromotion,
+    FromPly(usize),
+    Custom(Arc<CustomPredicate>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Material(material) => f.debug_tuple("Material").field(material).finish(),
+            Node::OppositeColoredBishops => f.write_str("OppositeColoredBishops"),
+            Node::Underpromotion => f.write_str("Underpromotion"),
+            Node::FromPly(ply) => f.debug_tuple("FromPly").field(ply).finish(),
+            Node::Custom(_) => f.write_str("Custom"),
+            Node::And(a, b) => f.debug_tuple("And").field(a).field(b).finish(),
+            Node::Or(a, b) => f.debug_tuple("Or").field(a).field(b).finish(),
+            Node::Not(a) => f.debug_tuple("Not").field(a).finish(),
+        }
+    }
+}
+
+impl Query {
+    /// Matches positions with exactly the given material, for either side. The material is
+    /// written as the pieces of one side, `v`, and the pieces of the other side, such as
+    /// `KRPvKR`; so `KRPvKR` also matches positions where Black has the extra pawn.
+    ///
+    /// Returns `None` if `material` cannot be parsed.
+    #[must_use]
+    pub fn material(material: &str) -> Option<Query> {
+        let (first, second) = material.split_once(['v', 'V'])?;
+        let side = |pieces: &str| -> Option<ByRole<u8>> {
+            let mut side = ByRole::<u8>::default();
+            for c in pieces.chars() {
+                let count = side.get_mut(Role::from_char(c.to_ascii_lowercase())?);
+                *count = count.checked_add(1)?;
+            }
+            (side.king == 1).then_some(side)
+        };
+        Some(Query(Node::Material(ByColor {
+            white: side(first)?,
+            black: side(second)?,
+        })))
+    }
+
+    /// Matches positions in which each side has exactly one bishop, and the bishops are on
+    /// squares of different colours.
+    #[must_use]
+    pub fn opposite_colored_bishops() -> Query {
+        Query(Node::OppositeColoredBishops)
+    }
+
+    /// Matches positions that were reached by a promotion to a knight, bishop or rook.
+    #[must_use]
+    pub fn underpromotion() -> Query {
+        Query(Node::Underpromotion)
+    }
+
+    /// Matches positions after both sides have played `n` moves, that is, from ply `2 * n` on.
+    #[must_use]
+    pub fn after_move(n: usize) -> Query {
+        Query(Node::FromPly(n.saturating_mul(2)))
+    }
+
+    /// Matches positions for which `predicate` returns `true`. The predicate is called with
+    /// the position and the move that led to it (`None` for the initial position).
+    #[must_use]
+    pub fn custom<F>(predicate: F) -> Query
+    where
+        F: Fn(&Chess, Option<Move>) -> bool + Send + Sync + 'static,
+    {
+        Query(Node::Custom(Arc::new(predicate)))
+    }
+
+    /// Matches positions that match both `self` and `other`.
+    #[must_use]
+    pub fn and(self, other: Query) -> Query {
+        Query(Node::And(Box::new(self.0), Box::new(other.0)))
+    }
+
+    /// Matches positions that match `self`, `other`, or both.
+    #[must_use]
+    pub fn or(self, other: Query) -> Query {
+        Query(Node::Or(Box::new(self.0), Box::new(other.0)))
+    }
+
+    /// Matches positions that do not match `self`.
+    #[must_use]
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Query {
+        Query(Node::Not(Box::new(self.0)))
+    }
+
+    /// Returns the first ply (0 being the initial position) at which the game that `decoder`
+    /// decodes matches the query, starting at its current position.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the game contains invalid moves before the first match.
+    pub fn first_match(&self, mut decoder: MoveByMoveDecoder<'_>) -> DecodeResult<Option<usize>> {
+        if self.0.matches(decoder.position(), None, decoder.ply()) {
+            return Ok(Some(decoder.ply()));
+        }
+        while let Some(m) = decoder.next_move() {
+            let m = m?;
+            if self.0.matches(decoder.position(), Some(m), decoder.ply()) {
+                return Ok(Some(decoder.ply()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Scans `games` one after the other, and returns the matching games with the first ply
+    /// at which they match, in order. The game ids are the indices of the games in `games`.
+    ///
+    /// Games are decoded lazily, as the returned iterator is advanced. A game that cannot be
+    /// decoded results in a [`SearchError::Decode`], after which the scan continues with
+    /// the next game.
+    pub fn scan<'q, I>(&'q self, games: I) -> impl Iterator<Item = SearchResult<SearchHit>> + 'q
+    where
+        I: IntoIterator,
+        I::Item: Borrow<EncodedGame>,
+        I::IntoIter: 'q,
+    {
+        games.into_iter().enumerate().filter_map(|(game, encoded)| {
+            match self.first_match(MoveByMoveDecoder::new(encoded.borrow())) {
+                Ok(ply) => ply.map(|ply| Ok(SearchHit { game, ply })),
+                Err(error) => Some(Err(SearchError::Decode { game, error })),
+            }
+        })
+    }
+}
+
+impl Node {
+    fn matches(&self, pos: &Chess, last_move: Option<Move>, ply: usize) -> bool {
+        match self {
+            Node::Material(material) => {
+                let actual = pos.board().material();
+                actual == *material || actual == material.into_swapped()
+            }
+            Node::OppositeColoredBishops => {
+                let board = pos.board();
+                let bishops = |color: Color| board.bishops() & board.by_color(color);
+                let (white, black) = (bishops(Color::White), bishops(Color::Black));
+                white.count() == 1
+                    && black.count() == 1
+                    && (white & Bitboard::DARK_SQUARES).any()
+                        != (black & Bitboard::DARK_SQUARES).any()
+            }
+            Node::Underpromotion => last_move
+                .and_then(|m| m.promotion())
+                .is_some_and(|role| role != Role::Queen),
+            Node::FromPly(from) => ply >= *from,
+            Node::Custom(predicate) => predicate(pos, last_move),
+            Node::And(a, b) => a.matches(pos, last_move, ply) && b.matches(pos, last_move, ply),
+            Node::Or(a, b) => a.matches(pos, last_move, ply) || b.matches(pos, last_move, ply),
+            Node::Not(a) => !a.matches(pos, last_move, ply),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_pgn;
+    use crate::test_util::{corrupt, encode_games};
+
+    fn hits(query: &Query, pgns: &[&str]) -> Vec<SearchHit> {
+        query
+            .scan(&encode_games(pgns))
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn material_query() {
+        assert!(Query::material("KRPvKR").is_some());
+        assert!(Query::material("KRP").is_none());
+        assert!(Query::material("KRPvKX").is_none());
+        assert!(Query::material("RPvKR").is_none());
+
+        // After 2. exd5 White is a pawn up, after 2... Qxd5 the material is equal again.
+        let pgn = "1. e4 d5 2. exd5 Qxd5";
+        let pawn_up = Query::material("KQRRBBNNPPPPPPPPvKQRRBBNNPPPPPPP").unwrap();
+        assert_eq!(hits(&pawn_up, &[pgn]), [SearchHit { game: 0, ply: 3 }]);
+        // Either side can have the extra pawn.
+        let pawn_down = Query::material("KQRRBBNNPPPPPPPvKQRRBBNNPPPPPPPP").unwrap();
+        assert_eq!(hits(&pawn_down, &[pgn]), [SearchHit { game: 0, ply: 3 }]);
+        let equal = Query::material("KQRRBBNNPPPPPPPvKQRRBBNNPPPPPPP").unwrap();
+        assert_eq!(hits(&equal, &[pgn]), [SearchHit { game: 0, ply: 4 }]);
+    }
+
+    #[test]
+    fn opposite_colored_bishops_after_move() {
+        // White gives up the light-squared bishop, Black the dark-squared one (on ply 9).
+        let pgn = "1. e4 e5 2. Bc4 Bc5 3. Bxf7+ Kxf7 4. d4 Bxd4 5. Qxd4 exd4 6. Nf3 Nf6 7. Nxd4";
+        let query = Query::opposite_colored_bishops();
+        assert_eq!(hits(&query, &[pgn]), [SearchHit { game: 0, ply: 9 }]);
+        assert_eq!(
+            hits(&query.clone().and(Query::after_move(6)), &[pgn]),
+            [SearchHit { game: 0, ply: 12 }]
+        );
+        assert!(hits(&query.and(Query::after_move(10)), &[pgn]).is_empty());
+    }
+
+    #[test]
+    fn combinators_and_errors() {
+        let any = Query::underpromotion().or(Query::underpromotion().not());
+        let mut broken = encode_pgn("1. e4").unwrap();
+        corrupt(&mut broken);
+        let games = [encode_pgn("1. e4").unwrap(), broken];
+
+        // The initial position already matches, before the invalid move.
+        let results: Vec<_> = any.scan(&games).map(Result::unwrap).collect();
+        assert_eq!(
+            results,
+            [SearchHit { game: 0, ply: 0 }, SearchHit { game: 1, ply: 0 }]
+        );
+
+        let results: Vec<_> = Query::underpromotion().scan(&games).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap_err().game(), 1);
+    }
+}
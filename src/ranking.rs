@@ -1,60 +1,92 @@
 use super::psqt;
-use shakmaty::{Chess, Color, Move, Piece, Position, Role, Square};
+use shakmaty::{Bitboard, Chess, Color, Move, Piece, Position, Role, Square};
 
 type Score = i32;
 
+// The capacity of a `shakmaty::MoveList`.
+const MAX_LEGAL_MOVES: usize = 256;
+
 pub fn move_rank(pos: &Chess, m: Move) -> Option<usize> {
     let legals = pos.legal_moves();
-    let mut counter = 0;
-    let score = move_score(pos, m);
-    let mut is_legal = false;
-    for lm in legals {
-        if is_legal || m != lm {
-            if score < move_score(pos, lm) {
-                counter += 1;
-            }
-        } else {
-            is_legal = true;
-        }
+    if !legals.contains(&m) {
+        return None;
     }
 
-    if is_legal { Some(counter) } else { None }
+    let scorer = Scorer::new(pos);
+    let score = scorer.score(m);
+    Some(
+        legals
+            .iter()
+            .filter(|&&lm| score < scorer.score(lm))
+            .count(),
+    )
 }
 
 pub fn nth_from_position(n: usize, pos: &Chess) -> Option<Move> {
     let legals = pos.legal_moves();
-    let mut scored_legals: Vec<(Move, Score)> =
-        legals.iter().map(|&m| (m, -move_score(pos, m))).collect();
-    if legals.len() > n {
-        let (_, m, _) = scored_legals.select_nth_unstable_by_key(n, |(_, score)| *score);
-        Some(m.0)
-    } else {
-        None
+    if n >= legals.len() {
+        return None;
+    }
+
+    let scorer = Scorer::new(pos);
+    if n == 0 {
+        // By far the most common rank, which does not need any selection.
+        return legals.iter().copied().max_by_key(|&m| scorer.score(m));
     }
+
+    // Every legal move has a different score, so the index of a move can be stored
+    // in the lower bits of its key (scores are never negative). That way no (move, score)
+    // vector has to be allocated.
+    let mut keys = [0_u64; MAX_LEGAL_MOVES];
+    for ((key, &m), index) in keys.iter_mut().zip(&legals).zip(0_u64..) {
+        *key = (u64::from(scorer.score(m).unsigned_abs()) << 8) | index;
+    }
+    let (_, key, _) = keys[..legals.len()].select_nth_unstable_by(n, |a, b| b.cmp(a));
+    #[allow(clippy::cast_possible_truncation)]
+    Some(legals[(*key & 0xff) as usize])
 }
 
-fn move_score(pos: &Chess, m: Move) -> Score {
-    let promotion = Score::from(m.promotion().unwrap_or(Role::Pawn)) - 1;
-    let capture = Score::from(m.is_capture());
-    let pawn_defense: Score = if any_defending_pawns(pos, m.to()) {
-        6 - Score::from(m.role())
-    } else {
-        6
-    };
-    let move_value = Score::from(512 + move_value(pos.turn(), m));
-    let to = Score::from(m.to());
-    let from = Score::from(m.from().expect("no drops"));
-
-    (promotion << 26)
-        + (capture << 25)
-        + (pawn_defense << 22)
-        + (move_value << 12)
-        + (to << 6)
-        + from
+/// Scores the moves in a position. Facts about the position that are the same for every move
+/// are computed once, when the scorer is constructed.
+struct Scorer {
+    turn: Color,
+    /// The squares attacked by the pawns of the side that is not to move.
+    pawn_defended: Bitboard,
 }
 
-fn any_defending_pawns(pos: &Chess, to: Square) -> bool {
-    (shakmaty::attacks::pawn_attacks(pos.turn(), to) & pos.board().pawns() & pos.them()).any()
+impl Scorer {
+    fn new(pos: &Chess) -> Self {
+        let them = !pos.turn();
+        let pawn_defended = (pos.board().pawns() & pos.them())
+            .into_iter()
+            .fold(Bitboard::EMPTY, |acc, sq| {
+                acc | shakmaty::attacks::pawn_attacks(them, sq)
+            });
+        Self {
+            turn: pos.turn(),
+            pawn_defended,
+        }
+    }
+
+    fn score(&self, m: Move) -> Score {
+        let promotion = Score::from(m.promotion().unwrap_or(Role::Pawn)) - 1;
+        let capture = Score::from(m.is_capture());
+        let pawn_defense: Score = if self.pawn_defended.contains(m.to()) {
+            6 - Score::from(m.role())
+        } else {
+            6
+        };
+        let move_value = Score::from(512 + move_value(self.turn, m));
+        let to = Score::from(m.to());
+        let from = Score::from(m.from().expect("no drops"));
+
+        (promotion << 26)
+            + (capture << 25)
+            + (pawn_defense << 22)
+            + (move_value << 12)
+            + (to << 6)
+            + from
+    }
 }
 
 // https://github.com/niklasf/rust-pgn-reader/blob/compression-with-spsa/examples/compression.rs#L121
@@ -122,23 +154,22 @@ mod tests {
     }
 
     #[test]
-    fn test_any_defending_pawns() {
-        assert!(any_defending_pawns(&Chess::default(), Square::E6));
+    fn test_pawn_defended() {
+        let scorer = Scorer::new(&Chess::default());
+        assert!(scorer.pawn_defended.contains(Square::E6));
+        assert!(!scorer.pawn_defended.contains(Square::E5));
     }
 
     #[test]
     fn test_move_score() {
         assert_eq!(
-            move_score(
-                &Chess::default(),
-                Move::Normal {
-                    role: Role::Pawn,
-                    from: Square::E2,
-                    to: Square::E4,
-                    capture: None,
-                    promotion: None,
-                }
-            ),
+            Scorer::new(&Chess::default()).score(Move::Normal {
+                role: Role::Pawn,
+                from: Square::E2,
+                to: Square::E4,
+                capture: None,
+                promotion: None,
+            }),
             (6 << 22) + (564 << 12) + (28 << 6) + 12
         );
     }
@@ -0,0 +1,425 @@
+use crate::{DecodeResult, EncodedGame, GameDecodeError, MoveByMoveDecoder};
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Bitboard, Board, ByColor, Chess, Color, EnPassantMode, Position, Rank, Role};
+use std::borrow::Borrow;
+use std::{fmt, io};
+
+/// The result of a search in a collection of games.
+pub type SearchResult<T> = Result<T, SearchError>;
+
+/// A game and ply at which a searched position was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SearchHit {
+    /// The index of the game in the searched collection of games.
+    pub game: usize,
+    /// The number of moves (plies) that were played when the position was reached, so 0 is
+    /// the initial position.
+    pub ply: usize,
+}
+
+/// Error when a game of a search cannot be decoded or read.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SearchError {
+    /// The game contains invalid moves.
+    Decode {
+        /// The index of the game in the collection.
+        game: usize,
+        /// Why the game could not be decoded.
+        error: GameDecodeError,
+    },
+    /// The game could not be read, for example from an archive.
+    Io {
+        /// The index of the game in the collection.
+        game: usize,
+        /// Why the game could not be read.
+        error: io::Error,
+    },
+}
+
+impl SearchError {
+    /// The index of the game in the collection.
+    #[must_use]
+    pub fn game(&self) -> usize {
+        match self {
+            SearchError::Decode { game, .. } | SearchError::Io { game, .. } => *game,
+        }
+    }
+}
+
+impl std::error::Error for SearchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SearchError::Decode { error, .. } => Some(error),
+            SearchError::Io { error, .. } => Some(error),
+        }
+    }
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Decode { game, error } => write!(f, "{error}, in game {game}"),
+            SearchError::Io { game, error } => write!(f, "Cannot read game {game}: {error}"),
+        }
+    }
+}
+
+/// Searches encoded games for a position, by comparing the Zobrist hashes of the positions
+/// in the games with the hash of the position that is searched for.
+///
+/// A game is only decoded until the position cannot be reached anymore: pawns and pieces
+/// are never added to the board (except for promotions, which use up a pawn), and pawns never
+/// return to their starting rank.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, PositionSearch, SearchHit};
+/// use shakmaty::{fen::Fen, CastlingMode, Chess};
+///
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let games = [
+///     encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5")?,
+///     encode_pgn("1. Nf3 Nc6 2. e4 e5 3. Bc4")?,
+///     encode_pgn("1. d4 d5")?,
+/// ];
+/// let target: Chess = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+///     .parse::<Fen>()?
+///     .into_position(CastlingMode::Standard)?;
+///
+/// let hits: Vec<SearchHit> = PositionSearch::new(&target)
+///     .search(&games)
+///     .collect::<Result<_, _>>()?;
+/// assert_eq!(hits, [SearchHit { game: 0, ply: 4 }, SearchHit { game: 1, ply: 4 }]);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PositionSearch {
+    hash: Zobrist64,
+    signature: Signature,
+}
+
+impl PositionSearch {
+    /// Prepares a search for `target`. En passant squares are only taken into account if
+    /// an en passant capture is legal ([`EnPassantMode::Legal`]).
+    #[must_use]
+    pub fn new(target: &Chess) -> Self {
+        Self {
+            hash: target.zobrist_hash(EnPassantMode::Legal),
+            signature: Signature::new(target.board()),
+        }
+    }
+
+    /// Returns the plies at which the position is reached in the game that `decoder`
+    /// decodes, starting at its current position.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the game contains invalid moves before the position
+    /// becomes unreachable.
+    pub fn matching_plies(&self, mut decoder: MoveByMoveDecoder<'_>) -> DecodeResult<Vec<usize>> {
+        let mut plies = vec![];
+        loop {
+            let pos = decoder.position();
+            if !Signature::new(pos.board()).can_reach(&self.signature) {
+                break;
+            }
+            if pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal) == self.hash {
+                plies.push(decoder.ply());
+            }
+            match decoder.next_move() {
+                Some(result) => result.map(drop)?,
+                None => break,
+            }
+        }
+        Ok(plies)
+    }
+
+    /// Searches `games` one after the other, and returns every game and ply at which the
+    /// position is reached, in order. The game ids are the indices of the games in `games`.
+    ///
+    /// Games are decoded lazily, as the returned iterator is advanced. A game that cannot be
+    /// decoded results in a [`SearchError::Decode`], after which the search continues with
+    /// the next game.
+    pub fn search<'s, I>(&'s self, games: I) -> impl Iterator<Item = SearchResult<SearchHit>> + 's
+    where
+        I: IntoIterator,
+        I::Item: Borrow<EncodedGame>,
+        I::IntoIter: 's,
+    {
+        games
+            .into_iter()
+            .enumerate()
+            .flat_map(|(game, encoded)| self.hits_in_game(game, encoded.borrow()))
+    }
+
+    /// Searches `games` in parallel, and returns every game and ply at which the position is
+    /// reached, in order. The game ids are the indices of the games in `games`.
+    ///
+    /// Like [`PositionSearch::search`], a game that cannot be decoded results in an error,
+    /// without stopping the search.
+    #[cfg(feature = "rayon")]
+    pub fn par_search<I>(&self, games: I) -> Vec<SearchResult<SearchHit>>
+    where
+        I: rayon::iter::IntoParallelIterator,
+        I::Iter: rayon::iter::IndexedParallelIterator,
+        I::Item: Borrow<EncodedGame>,
+    {
+        use rayon::prelude::*;
+
+        games
+            .into_par_iter()
+            .enumerate()
+            .with_min_len(PAR_CHUNK_LEN)
+            .flat_map_iter(|(game, encoded)| self.hits_in_game(game, encoded.borrow()))
+            .collect()
+    }
+
+    /// Searches all games of a memory-mapped archive in parallel, like
+    /// [`PositionSearch::par_search`]. The game ids are the game numbers in the archive.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] if the header or trailer of the
+    /// archive is invalid. Errors in single games are returned as items instead: a
+    /// [`SearchError::Io`] with the error of [`crate::MmapGameArchive::game`] if the game
+    /// cannot be read, or a [`SearchError::Decode`] if it cannot be decoded.
+    #[cfg(all(feature = "rayon", feature = "mmap"))]
+    pub fn par_search_archive(
+        &self,
+        archive: &crate::MmapGameArchive,
+    ) -> io::Result<Vec<SearchResult<SearchHit>>> {
+        use rayon::prelude::*;
+
+        Ok((0..archive.len()?)
+            .into_par_iter()
+            .with_min_len(PAR_CHUNK_LEN)
+            .flat_map_iter(|game| match archive.game(game) {
+                Ok(view) => hits(
+                    game,
+                    self.matching_plies(MoveByMoveDecoder::from_view(view)),
+                ),
+                // Deleted games have no hits.
+                Err(error) if error.kind() == io::ErrorKind::NotFound => vec![],
+                Err(error) => vec![Err(SearchError::Io { game, error })],
+            })
+            .collect())
+    }
+
+    fn hits_in_game(&self, game: usize, encoded: &EncodedGame) -> Vec<SearchResult<SearchHit>> {
+        hits(game, self.matching_plies(MoveByMoveDecoder::new(encoded)))
+    }
+}
+
+// The minimum number of games that a thread searches at once.
+#[cfg(feature = "rayon")]
+const PAR_CHUNK_LEN: usize = 64;
+
+fn hits(game: usize, plies: DecodeResult<Vec<usize>>) -> Vec<SearchResult<SearchHit>> {
+    match plies {
+        Ok(plies) => plies
+            .into_iter()
+            .map(|ply| Ok(SearchHit { game, ply }))
+            .collect(),
+        Err(error) => vec![Err(SearchError::Decode { game, error })],
+    }
+}
+
+/// The facts about a position that only change in one direction during a game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Signature {
+    // Per colour: the number of pawns, of knights, bishops, rooks and queens, and of all of them.
+    counts: ByColor<[u32; 6]>,
+    // Per colour: the pawns that are still on their starting rank.
+    unmoved_pawns: ByColor<Bitboard>,
+}
+
+impl Signature {
+    fn new(board: &Board) -> Self {
+        let counts = ByColor::new_with(|color| {
+            let ours = board.by_color(color);
+            let mut counts = [0; 6];
+            for (count, role) in counts.iter_mut().zip(Role::ALL) {
+                *count = (board.by_role(role) & ours).count() as u32;
+            }
+            // The king is always there, so count it as "all pieces" instead.
+            counts[5] = counts[..5].iter().sum();
+            counts
+        });
+        let unmoved_pawns = ByColor::new_with(|color| {
+            let home = match color {
+                Color::White => Rank::Second,
+                Color::Black => Rank::Seventh,
+            };
+            board.pawns() & board.by_color(color) & Bitboard::from_rank(home)
+        });
+        Self {
+            counts,
+            unmoved_pawns,
+        }
+    }
+
+    /// Whether a position with this signature can still lead to a position with the
+    /// `target` signature. This may return `true` for positions that cannot, but never
+    /// returns `false` for positions that can.
+    fn can_reach(&self, target: &Self) -> bool {
+        Color::ALL.into_iter().all(|color| {
+            let [pawns, ref pieces @ .., all] = *self.counts.get(color);
+            let [target_pawns, ref target_pieces @ .., target_all] = *target.counts.get(color);
+            // Every extra piece must come from a promotion, which uses up a pawn.
+            let promotions: u32 = pieces
+                .iter()
+                .zip(target_pieces)
+                .map(|(&have, &want)| want.saturating_sub(have))
+                .sum();
+            pawns >= target_pawns
+                && all >= target_all
+                && promotions <= pawns - target_pawns
+                && target
+                    .unmoved_pawns
+                    .get(color)
+                    .is_subset(*self.unmoved_pawns.get(color))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{corrupt, games};
+    use crate::{GameDecodeErrorKind, decode_game, encode_pgn};
+
+    // Searches without any pruning, for comparison.
+    fn naive_hits(target: &Chess, games: &[EncodedGame]) -> Vec<SearchHit> {
+        let hash: Zobrist64 = target.zobrist_hash(EnPassantMode::Legal);
+        let mut hits = vec![];
+        for (game, encoded) in games.iter().enumerate() {
+            let positions =
+                std::iter::once(Chess::default()).chain(decode_game(encoded).unwrap().1);
+            for (ply, pos) in positions.enumerate() {
+                if pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal) == hash {
+                    hits.push(SearchHit { game, ply });
+                }
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn search_matches_naive_search() {
+        let games = games();
+        // Every position of every game.
+        for encoded in &games {
+            let positions =
+                std::iter::once(Chess::default()).chain(decode_game(encoded).unwrap().1);
+            for target in positions {
+                let hits: Vec<_> = PositionSearch::new(&target)
+                    .search(&games)
+                    .map(Result::unwrap)
+                    .collect();
+                assert_eq!(hits, naive_hits(&target, &games));
+            }
+        }
+
+        let start = PositionSearch::new(&Chess::default());
+        let hits: Vec<_> = start.search(&games).map(Result::unwrap).collect();
+        // The second game repeats the initial position twice.
+        assert_eq!(hits.iter().filter(|hit| hit.game == 1).count(), 3);
+        assert_eq!(hits.len(), 6);
+    }
+
+    #[test]
+    fn signature_pruning() {
+        let (_, positions) = decode_game(&games()[0]).unwrap();
+        let start = Signature::new(Chess::default().board());
+        for pos in &positions {
+            let signature = Signature::new(pos.board());
+            assert!(start.can_reach(&signature));
+            assert!(signature.can_reach(&signature));
+        }
+        // After a capture or a pawn move, earlier positions are unreachable.
+        let after_e4 = Signature::new(positions[0].board());
+        assert!(!after_e4.can_reach(&start));
+        let after_capture = Signature::new(positions[7].board());
+        assert!(!after_capture.can_reach(&Signature::new(positions[6].board())));
+    }
+
+    #[test]
+    fn search_reports_errors_per_game() {
+        let mut games = games();
+        // The initial position remains reachable until the invalid code.
+        games[1] = encode_pgn("1. Nf3 Nf6 2. Ng1").unwrap();
+        corrupt(&mut games[1]);
+        let results: Vec<_> = PositionSearch::new(&Chess::default())
+            .search(&games)
+            .collect();
+        let errors: Vec<_> = results.iter().filter_map(|r| r.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].game(), 1);
+        assert!(matches!(
+            errors[0],
+            SearchError::Decode { error, .. } if error.kind == GameDecodeErrorKind::RankOutOfRange
+        ));
+        assert!(errors[0].to_string().ends_with(", in game 1"));
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, Ok(SearchHit { game: 3, ply: 0 })))
+        );
+    }
+
+    #[cfg(all(feature = "rayon", feature = "mmap"))]
+    #[test]
+    fn parallel_archive_search_reports_unreadable_games() {
+        use crate::archive::{INDEX_ENTRY_LEN, TRAILER_LEN};
+        use crate::test_util::TempDir;
+
+        let mut writer = crate::GameArchiveWriter::new(vec![]).unwrap();
+        for encoded in games() {
+            writer.add(&encoded).unwrap();
+        }
+        let mut bytes = writer.finish().unwrap();
+        // Corrupt the length in the index entry of game 1 (of 4).
+        let entry_len_at = bytes.len() - (TRAILER_LEN + 3 * INDEX_ENTRY_LEN) as usize + 8;
+        bytes[entry_len_at..entry_len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let dir = TempDir::new("search");
+        let path = dir.file("games.chga");
+        std::fs::write(&path, &bytes).unwrap();
+        // SAFETY: the file is only changed by this test.
+        let archive = unsafe { crate::MmapGameArchive::open(&path).unwrap() };
+        let results = PositionSearch::new(&Chess::default())
+            .par_search_archive(&archive)
+            .unwrap();
+
+        let errors: Vec<_> = results.iter().filter_map(|r| r.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        let SearchError::Io { game: 1, error } = errors[0] else {
+            panic!("expected an I/O error for game 1, got {:?}", errors[0]);
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, Ok(SearchHit { game: 3, ply: 0 })))
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_search() {
+        let games: Vec<EncodedGame> = games().into_iter().cycle().take(500).collect();
+        let (_, positions) = decode_game(&games[0]).unwrap();
+        let search = PositionSearch::new(&positions[3]);
+        let sequential: Vec<_> = search.search(&games).map(Result::unwrap).collect();
+        let parallel: Vec<_> = search
+            .par_search(&games)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel.len(), 250);
+    }
+}
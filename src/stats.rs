@@ -0,0 +1,406 @@
+use crate::codes::{self, WEIGHTS};
+use crate::{DecodeResult, EncodedGame, GameError, MoveByMoveDecoder};
+use shakmaty::{Chess, Position};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+/// Statistics about how well a set of encoded games is compressed, to see where the bits go
+/// and whether the codebook (built from the move rank weights of Lichess) still fits
+/// the games.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{encode_pgn, CompressionStats, GamePhase};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let games = [
+///     encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7")?,
+///     encode_pgn("1. a4 h5 2. Ra3 Rh6 3. Rg3 Rg6")?,
+/// ];
+/// let stats = CompressionStats::from_games(&games, 10)?;
+/// assert_eq!(stats.plies(), 16);
+/// println!("{:.2} bits per ply", stats.bits_per_ply());
+/// println!("{:.2} bits per ply could be saved", stats.cross_entropy_gap());
+/// assert_eq!(stats.worst_games()[0].game, 1);
+/// assert_eq!(stats.phase(GamePhase::Opening).plies, 16);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompressionStats {
+    max_worst_games: usize,
+    games: u64,
+    rank_counts: [u64; 256],
+    phases: [PhaseStats; 3],
+    // The worst compressed games, not sorted and possibly more than `max_worst_games`.
+    worst_games: Vec<GameCost>,
+}
+
+/// The phase of a game, as determined by a simplified version of the game divider of
+/// Lichess: the endgame starts when at most 6 pieces (other than kings and pawns) are left,
+/// and the middlegame after 10 moves, or earlier when at most 10 such pieces are left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamePhase {
+    /// The first moves, while most pieces are on the board.
+    Opening,
+    /// The rest of the game until the endgame.
+    Middlegame,
+    /// The part of the game with few pieces left.
+    Endgame,
+}
+
+/// The number of plies and bits of (a part of) the games.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseStats {
+    /// The number of plies.
+    pub plies: u64,
+    /// The number of bits the plies were encoded with.
+    pub bits: u64,
+}
+
+impl PhaseStats {
+    /// The average number of bits per ply, or 0 if there are no plies.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn bits_per_ply(&self) -> f64 {
+        if self.plies == 0 {
+            0.0
+        } else {
+            self.bits as f64 / self.plies as f64
+        }
+    }
+}
+
+/// The number of plies and bits of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameCost {
+    /// The id of the game.
+    pub game: usize,
+    /// The number of plies.
+    pub plies: usize,
+    /// The number of bits the game was encoded with.
+    pub bits: usize,
+}
+
+impl GameCost {
+    /// The average number of bits per ply, or 0 if the game has no plies.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn bits_per_ply(&self) -> f64 {
+        if self.plies == 0 {
+            0.0
+        } else {
+            self.bits as f64 / self.plies as f64
+        }
+    }
+
+    // Orders games by bits per ply, the highest first, and then by id.
+    fn worse(&self, other: &GameCost) -> Ordering {
+        (other.bits * self.plies)
+            .cmp(&(self.bits * other.plies))
+            .then(self.game.cmp(&other.game))
+    }
+}
+
+/// How often a move rank occurs in the games, compared with how often the codebook
+/// expects it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankFrequency {
+    /// The move rank.
+    pub rank: u8,
+    /// The number of plies with this rank.
+    pub count: u64,
+    /// The fraction of plies with this rank.
+    pub observed: f64,
+    /// The fraction of plies with this rank that the weights of the codebook imply.
+    pub expected: f64,
+    /// The length of the code of the rank.
+    pub code_len: u32,
+}
+
+impl CompressionStats {
+    /// Creates empty statistics, that keep track of the `max_worst_games` games with the
+    /// most bits per ply.
+    #[must_use]
+    pub fn new(max_worst_games: usize) -> Self {
+        Self {
+            max_worst_games,
+            games: 0,
+            rank_counts: [0; 256],
+            phases: [PhaseStats::default(); 3],
+            worst_games: vec![],
+        }
+    }
+
+    /// Collects the statistics of `games`, whose ids are their indices in `games`.
+    ///
+    /// # Errors
+    ///
+    /// [`GameError`] for the first game that cannot be decoded.
+    pub fn from_games<I>(games: I, max_worst_games: usize) -> Result<Self, GameError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<EncodedGame>,
+    {
+        let mut stats = Self::new(max_worst_games);
+        for (game, encoded) in games.into_iter().enumerate() {
+            stats
+                .add_game(game, encoded.borrow())
+                .map_err(|error| GameError { game, error })?;
+        }
+        Ok(stats)
+    }
+
+    /// Adds the statistics of a game with id `game`.
+    ///
+    /// # Errors
+    ///
+    /// [`crate::GameDecodeError`] if the game cannot be decoded. The game is then not
+    /// added at all.
+    pub fn add_game(&mut self, game: usize, encoded: &EncodedGame) -> DecodeResult<()> {
+        let mut plies = vec![];
+        let mut decoder = MoveByMoveDecoder::new(encoded);
+        loop {
+            let phase = phase(decoder.position(), decoder.ply());
+            let (_, rank, len) = match decoder.next_move_with_code() {
+                Some(decoded) => decoded?,
+                None => break,
+            };
+            plies.push((rank, len as usize, phase));
+        }
+
+        self.games += 1;
+        let mut bits = 0;
+        for &(rank, len, phase) in &plies {
+            self.rank_counts[rank as usize] += 1;
+            let phase = &mut self.phases[phase as usize];
+            phase.plies += 1;
+            phase.bits += len as u64;
+            bits += len;
+        }
+        if self.max_worst_games > 0 && !plies.is_empty() {
+            self.worst_games.push(GameCost {
+                game,
+                plies: plies.len(),
+                bits,
+            });
+            if self.worst_games.len() >= 2 * self.max_worst_games {
+                self.truncate_worst_games();
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of games.
+    #[must_use]
+    pub fn games(&self) -> u64 {
+        self.games
+    }
+
+    /// The number of plies of all games.
+    #[must_use]
+    pub fn plies(&self) -> u64 {
+        self.rank_counts.iter().sum()
+    }
+
+    /// The number of bits of all games.
+    #[must_use]
+    pub fn bits(&self) -> u64 {
+        self.phases.iter().map(|phase| phase.bits).sum()
+    }
+
+    /// The average number of bits per ply, or 0 if there are no plies.
+    #[must_use]
+    pub fn bits_per_ply(&self) -> f64 {
+        PhaseStats {
+            plies: self.plies(),
+            bits: self.bits(),
+        }
+        .bits_per_ply()
+    }
+
+    /// The number of plies and bits in a phase of the games.
+    #[must_use]
+    pub fn phase(&self, phase: GamePhase) -> PhaseStats {
+        self.phases[phase as usize]
+    }
+
+    /// Returns how often each move rank occurs, compared with the codebook, from rank 0 to
+    /// the highest rank that occurs.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn rank_histogram(&self) -> Vec<RankFrequency> {
+        let plies = self.plies().max(1) as f64;
+        let last = self.rank_counts.iter().rposition(|&count| count > 0);
+        (0..=u8::MAX)
+            .take(last.map_or(0, |last| last + 1))
+            .map(|rank| RankFrequency {
+                rank,
+                count: self.rank_counts[rank as usize],
+                observed: self.rank_counts[rank as usize] as f64 / plies,
+                expected: expected_frequency(rank),
+                code_len: codes::BOOK_FROM_LICHESS_WEIGHTS.code_len(rank),
+            })
+            .collect()
+    }
+
+    /// The entropy of the move ranks in bits per ply: the average number of bits per ply of
+    /// an ideal code for exactly these games.
+    #[must_use]
+    pub fn entropy(&self) -> f64 {
+        self.average_information(|frequency| frequency.observed)
+    }
+
+    /// The cross-entropy of the move ranks and the codebook weights in bits per ply: the
+    /// average number of bits per ply of an ideal code for the codebook weights.
+    #[must_use]
+    pub fn cross_entropy(&self) -> f64 {
+        self.average_information(|frequency| frequency.expected)
+    }
+
+    /// The difference between the cross-entropy and the entropy (the Kullback-Leibler
+    /// divergence) in bits per ply: about how much a codebook built from these games would
+    /// save.
+    #[must_use]
+    pub fn cross_entropy_gap(&self) -> f64 {
+        self.cross_entropy() - self.entropy()
+    }
+
+    /// The games with the most bits per ply, the worst first.
+    #[must_use]
+    pub fn worst_games(&self) -> Vec<GameCost> {
+        let mut stats = self.clone();
+        stats.truncate_worst_games();
+        stats.worst_games
+    }
+
+    // The average of `-log2(probability(rank))` over all plies.
+    fn average_information<F: Fn(&RankFrequency) -> f64>(&self, probability: F) -> f64 {
+        self.rank_histogram()
+            .iter()
+            .filter(|frequency| frequency.count > 0)
+            .map(|frequency| -frequency.observed * probability(frequency).log2())
+            .sum()
+    }
+
+    fn truncate_worst_games(&mut self) {
+        self.worst_games.sort_unstable_by(GameCost::worse);
+        self.worst_games.truncate(self.max_worst_games);
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn expected_frequency(rank: u8) -> f64 {
+    WEIGHTS[rank as usize] as f64 / WEIGHTS.iter().sum::<u64>() as f64
+}
+
+fn phase(pos: &Chess, ply: usize) -> GamePhase {
+    let board = pos.board();
+    let pieces = (board.occupied() & !board.kings() & !board.pawns()).count();
+    if pieces <= 6 {
+        GamePhase::Endgame
+    } else if pieces <= 10 || ply >= 20 {
+        GamePhase::Middlegame
+    } else {
+        GamePhase::Opening
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{corrupt, encode_games};
+    use shakmaty::CastlingMode;
+    use shakmaty::fen::Fen;
+
+    // A game that reaches the middlegame, an empty game and two short ones.
+    const GAMES: [&str; 4] = [
+        "1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 6. Re1 b5 7. Bb3 d6 8. c3 O-O \
+         9. h3 Nb8 10. d4 Nbd7 11. c4 c6 12. cxb5 axb5 13. Nc3 Bb7",
+        "",
+        "1. a4 h5 2. Ra3 Rh6 3. Rg3 Rg6 4. Rxg6 fxg6",
+        "1. d4 d5 2. c4 e6 3. Nc3 Nf6 4. Bg5 Be7",
+    ];
+
+    #[test]
+    fn stats_add_up() {
+        let games = encode_games(GAMES);
+        let stats = CompressionStats::from_games(&games, 2).unwrap();
+        assert_eq!(stats.games(), 4);
+        assert_eq!(stats.plies(), 26 + 8 + 8);
+        assert_eq!(
+            stats.bits(),
+            games.iter().map(|game| game.bit_index as u64).sum()
+        );
+
+        let histogram = stats.rank_histogram();
+        assert_eq!(
+            histogram.iter().map(|f| f.count).sum::<u64>(),
+            stats.plies()
+        );
+        assert_eq!(
+            histogram
+                .iter()
+                .map(|f| f.count * u64::from(f.code_len))
+                .sum::<u64>(),
+            stats.bits()
+        );
+        assert!(histogram.last().unwrap().count > 0);
+        assert!((histogram.iter().map(|f| f.observed).sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let opening = stats.phase(GamePhase::Opening);
+        let middlegame = stats.phase(GamePhase::Middlegame);
+        assert_eq!(middlegame.plies, 6);
+        assert_eq!(stats.phase(GamePhase::Endgame), PhaseStats::default());
+        assert_eq!(opening.bits + middlegame.bits, stats.bits());
+
+        // The cross-entropy is at least the entropy, and an ideal code for the codebook
+        // weights is never more than one bit per ply better than the codebook.
+        assert!(stats.cross_entropy_gap() >= 0.0);
+        assert!(stats.cross_entropy() > stats.bits_per_ply() - 1.0);
+
+        let worst = stats.worst_games();
+        assert_eq!(worst.len(), 2);
+        assert_eq!(worst[0].game, 2);
+        assert!(worst[0].bits_per_ply() >= worst[1].bits_per_ply());
+        assert!(
+            CompressionStats::from_games(&games, 0)
+                .unwrap()
+                .worst_games()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn game_phases() {
+        let phase_of = |fen: &str, ply| {
+            let pos: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+            phase(&pos, ply)
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(phase_of(start, 0), GamePhase::Opening);
+        assert_eq!(phase_of(start, 20), GamePhase::Middlegame);
+        let traded = "r3k1nr/pppq1ppp/8/8/8/8/PPPQ1PPP/R3K1NR w KQkq - 0 1";
+        assert_eq!(phase_of(traded, 8), GamePhase::Middlegame);
+        let endgame = "4k3/pppr1ppp/8/8/8/8/PPPR1PPP/4K3 w - - 0 1";
+        assert_eq!(phase_of(endgame, 8), GamePhase::Endgame);
+    }
+
+    #[test]
+    fn invalid_games_are_not_added() {
+        let mut games = encode_games(GAMES);
+        corrupt(&mut games[3]);
+        let err = CompressionStats::from_games(&games, 2).unwrap_err();
+        assert_eq!(err.game, 3);
+
+        let mut stats = CompressionStats::new(2);
+        assert!(stats.add_game(3, &games[3]).is_err());
+        assert_eq!(stats.games(), 0);
+        assert_eq!(stats.plies(), 0);
+    }
+}
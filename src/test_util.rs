@@ -0,0 +1,63 @@
+//! Fixtures shared by the unit tests of several modules.
+
+use crate::{EncodedGame, codes, encode_pgn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Games with captures and castling, one of which returns to the initial position twice, and
+/// two of which share their first moves.
+pub(crate) const GAMES: [&str; 4] = [
+    "1. e4 e5 2. Nf3 Nc6 3. Bb5 Nf6 4. O-O Nxe4 5. d4 Nd6 6. Bxc6 dxc6 7. dxe5 Nf5",
+    "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. e4",
+    "1. d4 d5 2. c4 dxc4 3. e4 e5",
+    "1. e4 e5 2. Nf3 Nc6",
+];
+
+/// [`GAMES`], encoded.
+pub(crate) fn games() -> Vec<EncodedGame> {
+    encode_games(GAMES)
+}
+
+/// Encodes games from PGNs that are known to be valid.
+pub(crate) fn encode_games<S: AsRef<str>>(pgns: impl IntoIterator<Item = S>) -> Vec<EncodedGame> {
+    pgns.into_iter()
+        .map(|pgn| encode_pgn(pgn.as_ref()).unwrap())
+        .collect()
+}
+
+/// Appends a code for a rank that is out of range in any position, so decoding fails with
+/// [`GameDecodeErrorKind::RankOutOfRange`](crate::GameDecodeErrorKind::RankOutOfRange) right
+/// after the moves the game already has.
+pub(crate) fn corrupt(encoded: &mut EncodedGame) {
+    codes::BOOK_FROM_LICHESS_WEIGHTS.encode(encoded, 100);
+}
+
+/// A fresh directory in the temporary directory, removed with its contents when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the directory, unique to `name` and the process.
+    pub(crate) fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("chess-huffman-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    /// The path of the directory.
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The path of the file `name` in the directory.
+    pub(crate) fn file(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,6 +1,7 @@
 use super::*;
+use crate::test_util::corrupt;
 use quickcheck_macros::quickcheck;
-use shakmaty::{Position, Role, Square};
+use shakmaty::{CastlingMode, Position, Role, Square};
 
 fn short_game_moves() -> Vec<Move> {
     vec![
@@ -72,6 +73,65 @@ fn expected_error() {
     assert!(encode_game(&moves).is_err());
 }
 
+#[test]
+fn error_location() {
+    let mut moves = short_game_moves();
+    moves.push(Move::Normal {
+        role: Role::Queen,
+        from: Square::D2,
+        to: Square::H6,
+        capture: None,
+        promotion: None,
+    });
+    let err = encode_game(&moves).unwrap_err();
+    assert_eq!(err.kind, GameEncodeErrorKind::IllegalMove);
+    assert_eq!(err.ply, Some(5));
+    assert_eq!(err.san, None);
+    assert_eq!(
+        err.fen.as_deref(),
+        Some("rnbq1bnr/ppppkppp/8/4P3/8/8/PPPQPPPP/RNB1KBNR b KQ - 2 3")
+    );
+
+    let err = encode_pgn("1. d4 e5 2. dxe5 Ke7 3. Qd2 Kd6").unwrap_err();
+    assert_eq!(err.kind, GameEncodeErrorKind::SanError);
+    assert_eq!(err.ply, Some(5));
+    assert_eq!(err.san.as_deref(), Some("Kd6"));
+    assert_eq!(err.byte_offset, None);
+
+    let err = GameEncodeError::from("Zz9".parse::<shakmaty::san::San>().unwrap_err());
+    assert_eq!(err.kind, GameEncodeErrorKind::ParseSanError);
+}
+
+#[test]
+fn lenient_encoding() {
+    let mut moves = short_game_moves();
+    let (encoded, error) = encode_game_lenient(&moves);
+    assert!(error.is_none());
+    assert_eq!(encoded, encode_game(&moves).unwrap());
+
+    moves.insert(
+        3,
+        Move::Normal {
+            role: Role::King,
+            from: Square::E8,
+            to: Square::E6,
+            capture: None,
+            promotion: None,
+        },
+    );
+    let (encoded, error) = encode_game_lenient(&moves);
+    assert_eq!(error.unwrap().ply, Some(3));
+    assert_eq!(decode_game(&encoded).unwrap().0, &moves[..3]);
+
+    let (encoded, error) = encode_pgn_lenient("1. d4 e5 2. dxe5 Ke7 3. Qc2 Kd6");
+    assert_eq!(error.unwrap().kind, GameEncodeErrorKind::SanError);
+    assert_eq!(decode_game(&encoded).unwrap().0, &short_game_moves()[..4]);
+
+    let (encoded, error) = encode_pgn_lenient("");
+    assert!(error.is_none());
+    assert_eq!(encoded.bit_index, 0);
+}
+
 #[test]
 fn encode_decode_consistency_pgn() {
     let moves = short_game_moves();
@@ -168,3 +228,312 @@ fn random_games_consistency(move_ids: Vec<u8>) -> bool {
         && decoded_moves == decoded_moves2
         && decoded_positions == decoded_positions2
 }
+
+#[test]
+fn decode_to_pgn_movetext() {
+    let encoded = encode_pgn("1. d4 e5 2. dxe5 Ke7 3. Qd2").unwrap();
+    assert_eq!(
+        decode_to_pgn(&encoded).unwrap(),
+        "1. d4 e5 2. dxe5 Ke7 3. Qd2 *\n"
+    );
+
+    let encoded = encode_pgn("1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7").unwrap();
+    assert_eq!(
+        decode_to_pgn(&encoded).unwrap(),
+        "1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n"
+    );
+
+    assert_eq!(decode_to_pgn(&encode_game(&[]).unwrap()).unwrap(), "*\n");
+}
+
+#[test]
+fn decode_to_pgn_roundtrip() {
+    let pgn = "1. Nf3 c5 2. g3 Nc6 3. Bg2 e5 4. O-O e4 5. Ne1 f5 6. d3 d5 7. dxe4 fxe4 8. Bf4
+Nf6 9. e3 Be7 10. Nd2 Bg4 11. c3 Bxd1 12. Rxd1 d4 13. Nc2 d3 14. Na3 O-O 15. Nb5
+a6 16. Bc7 axb5 17. Bxd8 Raxd8 18. Nxe4 Nxe4 19. Bxe4 c4 20. Bxc6 bxc6 21. Ra1
+Ra8 22. Rfd1 c5 23. a3 b4 24. cxb4 cxb4 25. a4 Rac8 26. Kf1 Rfd8 27. f4 c3
+28. bxc3 bxc3 29. Kf2 c2 30. Rdc1 Bf6 31. Ra2 d2 32. Raxc2 Rxc2 33. Rxc2 d1=Q
+0-1
+";
+    let tags = vec![
+        (String::from("Event"), String::from("Club \"Open\"")),
+        (String::from("Result"), String::from("0-1")),
+    ];
+
+    let mut out = vec![];
+    decode_to_pgn_writer(&encode_pgn(pgn).unwrap(), &tags, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with("[Event \"Club \\\"Open\\\"\"]\n[Result \"0-1\"]\n\n"));
+    assert!(out.lines().all(|line| line.len() <= 80));
+    assert!(out.ends_with(pgn));
+    assert_eq!(encode_pgn(&out).unwrap(), encode_pgn(pgn).unwrap());
+}
+
+#[test]
+fn decode_to_pgn_writer_streams_moves() {
+    let mut encoded = encode_pgn("1. e4 e5 2. Nf3").unwrap();
+    corrupt(&mut encoded);
+
+    let mut out = vec![];
+    let err = decode_to_pgn_writer(&encoded, &[], &mut out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(out, b"1. e4 e5 2. Nf3");
+}
+
+#[test]
+fn encode_pgn_games_stream() {
+    let pgn = "[Event \"Invalid\"]
+1. d4 e5 2. dxe5 Ke7 3. Qc2 *
+
+[Event \"Valid 1\"]
+[Site \"?\"]
+1. d4 e5 2. dxe5 Ke7 3. Qd2 *
+
+1. b4 e5 2. e4 Nf6 3. d3 *
+";
+
+    let results = encode_pgn_games(pgn.as_bytes()).collect::<Vec<_>>();
+    assert_eq!(results.len(), 3);
+    let err = results[0].as_ref().unwrap_err();
+    assert_eq!(err.kind, GameEncodeErrorKind::SanError);
+    assert_eq!(err.ply, Some(4));
+    assert_eq!(err.san.as_deref(), Some("Qc2"));
+    assert_eq!(err.byte_offset, Some(0));
+
+    let valid1 = results[1].as_ref().unwrap();
+    assert_eq!(
+        valid1.tags,
+        vec![
+            (String::from("Event"), String::from("Valid 1")),
+            (String::from("Site"), String::from("?")),
+        ]
+    );
+    assert_eq!(valid1.byte_offset, 49);
+    assert!(pgn[49..].starts_with("[Event \"Valid 1\"]"));
+    assert_eq!(decode_game(&valid1.encoded).unwrap().0, short_game_moves());
+
+    let valid2 = results[2].as_ref().unwrap();
+    assert!(valid2.tags.is_empty());
+    assert!(pgn[valid2.byte_offset as usize..].starts_with("1. b4"));
+}
+
+#[test]
+fn decode_error_incomplete_code() {
+    let moves = short_game_moves();
+    let complete = encode_game(&moves[..4]).unwrap();
+    let mut encoded = encode_game(&moves).unwrap();
+    encoded.bit_index -= 1;
+
+    let mut decoder = MoveByMoveDecoder::new(&encoded);
+    for _ in 0..4 {
+        decoder.next_move().unwrap().unwrap();
+    }
+    let err = decoder.next_move().unwrap().unwrap_err();
+    assert_eq!(err.kind, GameDecodeErrorKind::IncompleteCode);
+    assert_eq!(err.ply, 4);
+    assert_eq!(err.bit_offset, Some(complete.bit_index));
+    assert_eq!(*err.position, decode_game(&complete).unwrap().1[3]);
+    assert!(decoder.next_move().is_none());
+}
+
+#[test]
+fn decode_error_rank_out_of_range() {
+    let mut encoded = encode_game(&short_game_moves()[..1]).unwrap();
+    let bit_offset = encoded.bit_index;
+    corrupt(&mut encoded);
+
+    let err = decode_game(&encoded).unwrap_err();
+    assert_eq!(err.kind, GameDecodeErrorKind::RankOutOfRange);
+    assert_eq!(err.ply, 1);
+    assert_eq!(err.bit_offset, Some(bit_offset));
+    assert_eq!(err.position.board().role_at(Square::D4), Some(Role::Pawn));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Cannot decode invalid bit vector: move rank out of range at ply 1 (bit {bit_offset})"
+        )
+    );
+}
+
+#[test]
+fn lossy_decoding() {
+    let moves = short_game_moves();
+    let encoded = encode_game(&moves).unwrap();
+    let (lossy_moves, lossy_positions, error) = decode_game_lossy(&encoded);
+    assert_eq!(lossy_moves, moves);
+    assert_eq!(lossy_positions, decode_game(&encoded).unwrap().1);
+    assert!(error.is_none());
+
+    let mut damaged = encode_game(&moves[..2]).unwrap();
+    corrupt(&mut damaged);
+    let (lossy_moves, lossy_positions, error) = decode_game_lossy(&damaged);
+    assert_eq!(lossy_moves, &moves[..2]);
+    assert_eq!(lossy_positions.len(), 2);
+    assert_eq!(
+        lossy_positions[1].board().role_at(Square::E5),
+        Some(Role::Pawn)
+    );
+    let error = error.unwrap();
+    assert_eq!(error.kind, GameDecodeErrorKind::RankOutOfRange);
+    assert_eq!(error.ply, 2);
+}
+
+#[test]
+fn strict_decoding() {
+    let moves = short_game_moves();
+    let bytes = encode_game(&moves).unwrap().to_bytes();
+    let encoded = EncodedGame::from_bytes_strict(&bytes).unwrap();
+    assert_eq!(decode_game_strict(&encoded).unwrap().0, moves);
+    // The unused bits of the padding byte are not checked.
+    let mut with_metadata = bytes.clone();
+    *with_metadata.last_mut().unwrap() |= 0b1100_0000;
+    assert!(EncodedGame::from_bytes_strict(&with_metadata).is_ok());
+    // Encoders keep unused zero words around, which are fine as well.
+    assert!(decode_game_strict(&encode_game(&moves).unwrap()).is_ok());
+
+    for invalid in [&[][..], &[3], &bytes[1..], &[&bytes[..], &[0]].concat()] {
+        assert_eq!(
+            EncodedGame::from_bytes_strict(invalid).unwrap_err().kind,
+            GameDecodeErrorKind::InvalidLength
+        );
+    }
+
+    let mut padded = encode_game(&moves).unwrap();
+    padded.inner.push(1 << 5);
+    let err = decode_game_strict(&padded).unwrap_err();
+    assert_eq!(err.kind, GameDecodeErrorKind::NonZeroPadding);
+    assert_eq!(err.ply, moves.len());
+    assert_eq!(err.bit_offset, Some((padded.inner.len() - 1) * 64 + 5));
+}
+
+#[test]
+fn uci_roundtrip() {
+    let uci = "e2e4 d7d5 e4d5 g8f6 f1b5 c8d7 b5d7 d8d7 g1f3 b8c6 e1g1 e8c8 d5c6 d7e6 c6b7 c8d7 \
+               b7b8q";
+    let encoded = encode_uci(uci).unwrap();
+    assert_eq!(
+        encoded,
+        encode_pgn(
+            "1. e4 d5 2. exd5 Nf6 3. Bb5 Bd7 4. Bxd7+ Qxd7 5. Nf3 Nc6 6. O-O O-O-O 7. dxc6 Qe6
+            8. cxb7+ Kd7 9. b8=Q"
+        )
+        .unwrap()
+    );
+
+    let standard = MoveByMoveDecoder::new(&encoded)
+        .into_iter_uci(CastlingMode::Standard)
+        .map(|r| r.unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        standard.join(" "),
+        uci.split_whitespace().collect::<Vec<_>>().join(" ")
+    );
+
+    let chess960 = MoveByMoveDecoder::new(&encoded)
+        .into_iter_uci(CastlingMode::Chess960)
+        .map(|r| r.unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(chess960[10], "e1h1");
+    assert_eq!(chess960[11], "e8a8");
+    assert_eq!(encode_uci(chess960.join(" ")).unwrap(), encoded);
+}
+
+#[test]
+fn uci_errors() {
+    let err = encode_uci("e2e4 e7e5 e1e3").unwrap_err();
+    assert_eq!(err.kind, GameEncodeErrorKind::UciError);
+    assert_eq!(err.uci.as_deref(), Some("e1e3"));
+    assert!(err.to_string().contains("(move e1e3)"));
+    assert_eq!(err.ply, Some(2));
+
+    let err = encode_uci("e2e4 e7e9").unwrap_err();
+    assert_eq!(err.kind, GameEncodeErrorKind::ParseUciError);
+    assert_eq!(err.uci.as_deref(), Some("e7e9"));
+    assert_eq!(err.ply, Some(1));
+}
+
+#[test]
+fn seek_to_ply_consistency() {
+    let encoded = encode_pgn(
+        "1. Nf3 c5 2. g3 Nc6 3. Bg2 e5 4. O-O e4 5. Ne1 f5 6. d3 d5 7. dxe4 fxe4 8. Bf4 Nf6
+        9. e3 Be7 10. Nd2 Bg4 11. c3 Bxd1 12. Rxd1 d4 13. Nc2 d3 14. Na3 O-O 15. Nb5 a6",
+    )
+    .unwrap();
+    let (moves, positions) = decode_game(&encoded).unwrap();
+
+    let mut decoder = MoveByMoveDecoder::new(&encoded);
+    for ply in [5, 17, 18, 3, 0, 29, 30] {
+        assert!(decoder.seek_to_ply(ply).unwrap());
+        assert_eq!(decoder.ply(), ply);
+        if ply > 0 {
+            assert_eq!(decoder.position(), &positions[ply - 1]);
+        }
+        if ply < moves.len() {
+            assert_eq!(decoder.next_move().unwrap().unwrap(), moves[ply]);
+        }
+    }
+    assert!(!decoder.seek_to_ply(31).unwrap());
+    assert_eq!(decoder.ply(), 30);
+
+    let mut decoder = MoveByMoveDecoder::new(&encoded);
+    assert_eq!(decoder.skip(10).unwrap(), 10);
+    assert_eq!(decoder.skip(100).unwrap(), 20);
+    assert_eq!(decoder.skip(1).unwrap(), 0);
+}
+
+#[test]
+fn seek_to_ply_error() {
+    let mut encoded = encode_game(&short_game_moves()).unwrap();
+    encoded.bit_index -= 1;
+
+    let mut decoder = MoveByMoveDecoder::new(&encoded);
+    assert!(decoder.seek_to_ply(4).unwrap());
+    assert_eq!(decoder.seek_to_ply(5).unwrap_err().ply, 4);
+}
+
+#[test]
+fn san_iterator_consistency() {
+    let encoded = encode_pgn(
+        "1. e4 d5 2. exd5 Nf6 3. Bb5+ Bd7 4. Bxd7+ Qxd7 5. Nf3 Nc6 6. O-O O-O-O 7. dxc6 Qe6
+        8. cxb7+ Kd7 9. b8=Q",
+    )
+    .unwrap();
+
+    let (moves, positions) = decode_game(&encoded).unwrap();
+    let expected = moves
+        .iter()
+        .zip(std::iter::once(&Chess::default()).chain(&positions))
+        .map(|(&m, pos)| shakmaty::san::SanPlus::from_move(pos.clone(), m))
+        .collect::<Vec<_>>();
+    let san = MoveByMoveDecoder::new(&encoded)
+        .into_iter_san()
+        .map(|r| r.unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(san, expected);
+    assert_eq!(san[4].to_string(), "Bb5+");
+    assert_eq!(san[11].to_string(), "O-O-O");
+    assert_eq!(san[16].to_string(), "b8=Q");
+}
+
+#[test]
+fn zobrist_iterator_consistency() {
+    let encoded = encode_pgn("1. e4 c5 2. e5 d5 3. exd6 Nf6 4. Nf3 Ng8 5. Ng1 Nf6").unwrap();
+
+    let hashes = MoveByMoveDecoder::new(&encoded)
+        .into_iter_zobrist()
+        .map(|r| r.unwrap())
+        .collect::<Vec<_>>();
+    let expected = decode_game(&encoded)
+        .unwrap()
+        .1
+        .iter()
+        .map(|pos| {
+            shakmaty::zobrist::ZobristHash::zobrist_hash(pos, shakmaty::EnPassantMode::Legal)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(hashes, expected);
+    assert_eq!(hashes[5], hashes[9]);
+}
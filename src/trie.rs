@@ -0,0 +1,425 @@
+use crate::codes::{self, BitSource};
+use crate::position_index::{read_varint, write_varint};
+use crate::{EncodedGame, GameDecodeError, GameDecodeErrorKind, GameError, MoveByMoveDecoder};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+const MAGIC: [u8; 4] = *b"CHGT";
+const VERSION: u16 = 1;
+
+/// A collection of encoded games that stores the openings the games have in common only once.
+///
+/// The move ranks at the start of the games (the symbols of their Huffman codes) form a trie.
+/// Every path in the trie that is shared by at least two games is stored once, as a node per
+/// move rank. A game is stored as a reference to the deepest shared node on its path, plus
+/// the bits of its remaining moves.
+///
+/// # Examples
+///
+/// ```
+/// # use chess_huffman::{decode_to_pgn, encode_pgn, GameTrie};
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+/// let games = [
+///     encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7")?,
+///     encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Bxc6 dxc6")?,
+///     encode_pgn("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5")?,
+/// ];
+/// let trie = GameTrie::build(&games, 20)?;
+/// assert_eq!(decode_to_pgn(&trie.game(2))?, "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 *\n");
+///
+/// let bytes = trie.to_bytes();
+/// assert_eq!(GameTrie::from_bytes(&bytes).unwrap(), trie);
+/// # Ok(())
+/// # }
+/// # try_main().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameTrie {
+    // Node `i + 1` is `nodes[i]`; node 0 is the root (the initial position). Parents always
+    // come before their children.
+    nodes: Vec<TrieNode>,
+    games: Vec<TrieGame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TrieNode {
+    parent: u32,
+    rank: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrieGame {
+    node: u32,
+    suffix: EncodedGame,
+}
+
+/// How much space a [`GameTrie`] saves over storing every game on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrieStats {
+    /// The number of games.
+    pub games: usize,
+    /// The number of shared move ranks stored in the trie.
+    pub shared_nodes: usize,
+    /// The total size of the games in the format of [`EncodedGame::to_bytes`].
+    pub per_game_bytes: usize,
+    /// The size of the trie in the format of [`GameTrie::to_bytes`].
+    pub trie_bytes: usize,
+}
+
+impl TrieStats {
+    /// The fraction of the per-game size that the trie saves, which is negative if
+    /// the trie is larger.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn gain(&self) -> f64 {
+        if self.per_game_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.trie_bytes as f64 / self.per_game_bytes as f64
+        }
+    }
+}
+
+impl GameTrie {
+    /// Builds a trie of `games`, sharing at most the first `max_depth` moves of each game.
+    ///
+    /// # Errors
+    ///
+    /// [`GameError`] if the first `max_depth` codes of a game are invalid.
+    pub fn build<I>(games: I, max_depth: usize) -> Result<Self, GameError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<EncodedGame>,
+    {
+        // The full trie of the first moves of all games, with the number of games per node.
+        let mut children: HashMap<(u32, u8), u32> = HashMap::new();
+        let mut full_nodes = vec![TrieNode { parent: 0, rank: 0 }];
+        let mut counts = vec![0_usize];
+        // Per game: its path in the full trie, and the bit offset after each move on the path.
+        let mut paths = vec![];
+
+        for (game, encoded) in games.into_iter().enumerate() {
+            let encoded = encoded.borrow();
+            let mut node = 0;
+            let mut path = vec![(0, 0)];
+            counts[0] += 1;
+            while path.len() <= max_depth {
+                let bit_offset = path.last().map_or(0, |&(_, offset)| offset);
+                let Some(symbol) = codes::BOOK_FROM_LICHESS_WEIGHTS.decode(
+                    BitSource::Words(&encoded.inner),
+                    bit_offset,
+                    encoded.bit_index,
+                ) else {
+                    break;
+                };
+                let (rank, len) = symbol.map_err(|kind| GameError {
+                    game,
+                    error: decode_error(encoded, kind, path.len() - 1, bit_offset),
+                })?;
+                node = *children.entry((node, rank)).or_insert_with(|| {
+                    full_nodes.push(TrieNode { parent: node, rank });
+                    counts.push(0);
+                    to_u32(full_nodes.len() - 1)
+                });
+                counts[node as usize] += 1;
+                path.push((node, bit_offset + len as usize));
+            }
+            paths.push((path, encoded.clone()));
+        }
+
+        // Only keep the nodes that are shared by at least two games. Since a node is shared
+        // by at least as many games as its children, this keeps whole paths from the root.
+        let mut new_ids = vec![0; full_nodes.len()];
+        let mut nodes = vec![];
+        for (id, node) in full_nodes.iter().enumerate().skip(1) {
+            if counts[id] >= 2 {
+                nodes.push(TrieNode {
+                    parent: new_ids[node.parent as usize],
+                    rank: node.rank,
+                });
+                new_ids[id] = to_u32(nodes.len());
+            }
+        }
+
+        let games = paths
+            .into_iter()
+            .map(|(path, encoded)| {
+                let &(node, bit_offset) = path
+                    .iter()
+                    .rev()
+                    .find(|&&(node, _)| node == 0 || new_ids[node as usize] != 0)
+                    .expect("the root is on every path");
+                TrieGame {
+                    node: new_ids[node as usize],
+                    suffix: copy_bits(&encoded, bit_offset, encoded.bit_index),
+                }
+            })
+            .collect();
+
+        Ok(Self { nodes, games })
+    }
+
+    /// The number of games in the trie.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    /// Whether the trie contains no games.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// Reconstructs the game with index `n`, with exactly the same bits as the game that was
+    /// added to the trie.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not lower than the number of games in the trie.
+    #[must_use]
+    pub fn game(&self, n: usize) -> EncodedGame {
+        let game = &self.games[n];
+        let mut ranks = vec![];
+        let mut node = game.node;
+        while node != 0 {
+            let TrieNode { parent, rank } = self.nodes[node as usize - 1];
+            ranks.push(rank);
+            node = parent;
+        }
+
+        let mut encoded = EncodedGame::new();
+        for &rank in ranks.iter().rev() {
+            codes::BOOK_FROM_LICHESS_WEIGHTS.encode(&mut encoded, rank);
+        }
+        append_bits(&mut encoded, &game.suffix, 0, game.suffix.bit_index);
+        encoded
+    }
+
+    /// Compares the size of the trie with the size of its games stored one by one.
+    #[must_use]
+    pub fn stats(&self) -> TrieStats {
+        TrieStats {
+            games: self.games.len(),
+            shared_nodes: self.nodes.len(),
+            per_game_bytes: (0..self.len()).map(|n| self.game(n).to_bytes().len()).sum(),
+            trie_bytes: self.to_bytes().len(),
+        }
+    }
+
+    /// Converts the trie to a byte vector. Use `from_bytes` to convert the result back to
+    /// a [`GameTrie`].
+    ///
+    /// The bytes are the magic bytes `CHGT`, the format version (`u16`) and two reserved
+    /// bytes, followed by the number of nodes, a bitmap with a bit per node that is set if
+    /// the parent of the node is the previous node, and the differences between the indices
+    /// of the other nodes and their parents. Then come the number of games and, per game,
+    /// the index of its node and the number of bits of its remaining moves. The rest is a
+    /// single bit stream in the format of [`EncodedGame::to_bytes`], with the codes of the
+    /// move ranks of all nodes followed by the remaining moves of all games. Numbers are
+    /// varints.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.write_u16::<LittleEndian>(VERSION).unwrap();
+        out.write_u16::<LittleEndian>(0).unwrap();
+
+        write_varint(&mut out, self.nodes.len() as u64);
+        let mut bitmap = vec![0_u8; self.nodes.len().div_ceil(8)];
+        let mut deltas = vec![];
+        let mut bits = EncodedGame::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.parent as usize == i {
+                bitmap[i / 8] |= 1 << (i % 8);
+            } else {
+                write_varint(&mut deltas, (i + 1) as u64 - u64::from(node.parent));
+            }
+            codes::BOOK_FROM_LICHESS_WEIGHTS.encode(&mut bits, node.rank);
+        }
+        out.extend_from_slice(&bitmap);
+        out.extend_from_slice(&deltas);
+
+        write_varint(&mut out, self.games.len() as u64);
+        for game in &self.games {
+            write_varint(&mut out, u64::from(game.node));
+            write_varint(&mut out, game.suffix.bit_index as u64);
+            append_bits(&mut bits, &game.suffix, 0, game.suffix.bit_index);
+        }
+        out.extend_from_slice(&bits.to_bytes());
+        out
+    }
+
+    /// Converts a byte vector (that was the output of `to_bytes`) to a [`GameTrie`].
+    /// Returns `None` if the bytes are not a valid trie.
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 8 || bytes[..4] != MAGIC || LittleEndian::read_u16(&bytes[4..6]) != VERSION
+        {
+            return None;
+        }
+        let mut rdr = &bytes[8..];
+
+        let node_count = usize::try_from(read_varint(&mut rdr)?).ok()?;
+        if node_count >= u32::MAX as usize || node_count.div_ceil(8) > rdr.len() {
+            return None;
+        }
+        let (bitmap, rest) = rdr.split_at(node_count.div_ceil(8));
+        rdr = rest;
+        let mut parents = Vec::with_capacity(node_count);
+        for i in 0..node_count {
+            if bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                parents.push(to_u32(i));
+            } else {
+                let delta = usize::try_from(read_varint(&mut rdr)?).ok()?;
+                parents.push(to_u32((i + 1).checked_sub(delta).filter(|_| delta > 0)?));
+            }
+        }
+
+        let game_count = usize::try_from(read_varint(&mut rdr)?).ok()?;
+        let mut lengths = Vec::with_capacity(game_count.min(rdr.len()));
+        for _ in 0..game_count {
+            let node = u32::try_from(read_varint(&mut rdr)?).ok()?;
+            let len = usize::try_from(read_varint(&mut rdr)?).ok()?;
+            if node as usize > node_count {
+                return None;
+            }
+            lengths.push((node, len));
+        }
+
+        let bits = EncodedGame::from_bytes_strict(rdr).ok()?;
+        let source = BitSource::Words(&bits.inner);
+        let mut bit_offset = 0;
+        let mut nodes = Vec::with_capacity(node_count);
+        for parent in parents {
+            let (rank, len) = codes::BOOK_FROM_LICHESS_WEIGHTS
+                .decode(source, bit_offset, bits.bit_index)?
+                .ok()?;
+            nodes.push(TrieNode { parent, rank });
+            bit_offset += len as usize;
+        }
+        let mut games = Vec::with_capacity(game_count);
+        for (node, len) in lengths {
+            let end = bit_offset
+                .checked_add(len)
+                .filter(|&end| end <= bits.bit_index)?;
+            games.push(TrieGame {
+                node,
+                suffix: copy_bits(&bits, bit_offset, end),
+            });
+            bit_offset = end;
+        }
+
+        (bit_offset == bits.bit_index).then_some(Self { nodes, games })
+    }
+}
+
+// The error for the code of ply `ply` of `encoded`, at `bit_offset`, that could not be decoded.
+// The moves before it are decoded to find the position the error happened in (or an earlier
+// error, since building the trie only looks at the codes).
+fn decode_error(
+    encoded: &EncodedGame,
+    kind: GameDecodeErrorKind,
+    ply: usize,
+    bit_offset: usize,
+) -> GameDecodeError {
+    let mut decoder = MoveByMoveDecoder::new(encoded);
+    match decoder.skip(ply) {
+        Ok(_) => GameDecodeError::new(kind, ply, bit_offset, decoder.position()),
+        Err(e) => e,
+    }
+}
+
+fn to_u32(n: usize) -> u32 {
+    u32::try_from(n).expect("tries are limited to 2^32 nodes")
+}
+
+// Returns a game with the bits `from..to` of `src`.
+fn copy_bits(src: &EncodedGame, from: usize, to: usize) -> EncodedGame {
+    let mut copy = EncodedGame::new();
+    append_bits(&mut copy, src, from, to);
+    copy
+}
+
+// Appends the bits `from..to` of `src` to `dst`.
+fn append_bits(dst: &mut EncodedGame, src: &EncodedGame, from: usize, to: usize) {
+    let source = BitSource::Words(&src.inner);
+    let mut offset = from;
+    while offset < to {
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (to - offset).min(32) as u32;
+        codes::push_bits(dst, source.peek(offset), len);
+        offset += len as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::encode_games;
+    use crate::{decode_game, encode_pgn};
+
+    const OPENINGS: [&str; 3] = [
+        "1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 6. Re1 b5 7. Bb3 d6 8. c3 O-O",
+        "1. d4 Nf6 2. c4 e6 3. Nc3 Bb4 4. e3 O-O 5. Bd3 d5 6. Nf3 c5 7. O-O Nc6",
+        "1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6 6. Be3 e5 7. Nb3 Be6",
+    ];
+    const CONTINUATIONS: [&str; 6] = ["", "a3", "h3 h6", "g3 g6 h4", "a4", "h4 h5"];
+
+    fn games() -> Vec<EncodedGame> {
+        let games = OPENINGS.iter().flat_map(|opening| {
+            CONTINUATIONS.map(|continuation| format!("{opening} {continuation}"))
+        });
+        // A game that shares nothing, and an empty game.
+        encode_games(games.chain(["1. b3 e5".to_owned(), String::new()]))
+    }
+
+    #[test]
+    fn trie_roundtrip() {
+        let games = games();
+        for max_depth in [0, 1, 5, 20, 100] {
+            let trie = GameTrie::build(&games, max_depth).unwrap();
+            assert_eq!(trie.len(), games.len());
+            for (n, game) in games.iter().enumerate() {
+                assert_eq!(trie.game(n).to_bytes(), game.to_bytes());
+            }
+            assert_eq!(GameTrie::from_bytes(&trie.to_bytes()).unwrap(), trie);
+        }
+    }
+
+    #[test]
+    fn trie_saves_space() {
+        let games = games();
+        let stats = GameTrie::build(&games, 20).unwrap().stats();
+        assert_eq!(stats.games, games.len());
+        assert_eq!(
+            stats.per_game_bytes,
+            games.iter().map(|game| game.to_bytes().len()).sum()
+        );
+        // The openings are at least 12 plies long, so at least 12 nodes per opening.
+        assert!(stats.shared_nodes >= 3 * 12);
+        assert!(stats.gain() > 0.3, "{stats:?}");
+
+        let no_sharing = GameTrie::build(&games, 0).unwrap().stats();
+        assert_eq!(no_sharing.shared_nodes, 0);
+        assert!(no_sharing.gain() < 0.0);
+    }
+
+    #[test]
+    fn invalid_tries() {
+        let trie = GameTrie::build(games(), 20).unwrap();
+        let bytes = trie.to_bytes();
+        assert!(GameTrie::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        assert!(GameTrie::from_bytes(&[&bytes[..], &[0]].concat()).is_none());
+        assert!(GameTrie::from_bytes(&bytes[1..]).is_none());
+
+        let e4 = encode_pgn("1. e4").unwrap();
+        let mut broken = e4.clone();
+        codes::BOOK_FROM_LICHESS_WEIGHTS.encode(&mut broken, 10);
+        broken.bit_index -= 1;
+        let err = GameTrie::build([e4.clone(), broken], 20).unwrap_err();
+        assert_eq!(err.game, 1);
+        assert_eq!(err.error.ply, 1);
+        assert_eq!(*err.error.position, decode_game(&e4).unwrap().1[0]);
+    }
+}